@@ -0,0 +1,48 @@
+//! Standard bootstrap for the daemons in this workspace, so that they all
+//! log, schedule tasks, and shut down the same way
+
+use std::fmt::Display;
+use std::process::ExitCode;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::LocalSet;
+
+/// Runs a daemon to completion. Sets up logging, then runs `main` on a
+/// current thread runtime within a [`LocalSet`], so it can `spawn_local`.
+/// Exits successfully on SIGTERM or SIGINT, otherwise logs any error
+/// `main` returns and exits with failure
+pub fn run<E: Display>(main: impl Future<Output = Result<(), E>>) -> ExitCode {
+    samsunghvac_common::log::init();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("building runtime");
+
+    LocalSet::new().block_on(&runtime, async {
+        tokio::select! {
+            result = main => match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(err) => {
+                    log::error!("{err}");
+                    ExitCode::FAILURE
+                }
+            },
+            signal = shutdown_signal() => {
+                log::info!("received {signal}, exiting");
+                ExitCode::SUCCESS
+            }
+        }
+    })
+}
+
+/// Waits for SIGTERM or SIGINT, returning its name
+async fn shutdown_signal() -> &'static str {
+    let mut term = signal(SignalKind::terminate()).expect("installing SIGTERM handler");
+    let mut int = signal(SignalKind::interrupt()).expect("installing SIGINT handler");
+
+    tokio::select! {
+        _ = term.recv() => "SIGTERM",
+        _ = int.recv() => "SIGINT",
+    }
+}
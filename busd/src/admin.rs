@@ -0,0 +1,110 @@
+//! Admin interface, served on a separate unix socket. Each connection sends
+//! a single command line and receives a reply, then is closed:
+//!
+//! - `peers`: lists connected peers, one per line
+//! - `stats`: reports forwarding latency, one `name value` pair per line
+//! - `disconnect <id>`: disconnects the client with the given id
+//! - `shutdown`: stops the daemon
+
+use std::str::FromStr;
+
+use samsunghvac_client::transport;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+pub use transport::DEFAULT_ADMIN_SOCKET;
+
+pub enum AdminRequest {
+    Peers,
+    Stats,
+    Disconnect { id: u32 },
+    Shutdown,
+}
+
+#[derive(Error, Debug)]
+pub enum InvalidRequest {
+    #[error("unknown command: {0}")]
+    UnknownCommand(String),
+    #[error("expected client id")]
+    MissingId,
+    #[error("invalid client id: {0}")]
+    InvalidId(String),
+}
+
+impl FromStr for AdminRequest {
+    type Err = InvalidRequest;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+
+        match words.next().unwrap_or_default() {
+            "peers" => Ok(AdminRequest::Peers),
+            "stats" => Ok(AdminRequest::Stats),
+            "shutdown" => Ok(AdminRequest::Shutdown),
+            "disconnect" => {
+                let id = words.next().ok_or(InvalidRequest::MissingId)?;
+                let id = id.parse().map_err(|_| InvalidRequest::InvalidId(id.to_owned()))?;
+                Ok(AdminRequest::Disconnect { id })
+            }
+            command => Err(InvalidRequest::UnknownCommand(command.to_owned())),
+        }
+    }
+}
+
+pub struct AdminCommand {
+    pub request: AdminRequest,
+    pub reply: oneshot::Sender<String>,
+}
+
+pub fn start_admin(listen: UnixListener) -> mpsc::Receiver<AdminCommand> {
+    let (tx, rx) = mpsc::channel(8);
+    tokio::task::spawn(accept_task(listen, tx));
+    rx
+}
+
+async fn accept_task(listen: UnixListener, tx: mpsc::Sender<AdminCommand>) {
+    loop {
+        let (conn, _) = match listen.accept().await {
+            Ok(result) => result,
+            Err(err) => {
+                log::error!("admin accept: {err}");
+                break;
+            }
+        };
+
+        tokio::task::spawn(connection_task(conn, tx.clone()));
+    }
+}
+
+async fn connection_task(conn: UnixStream, tx: mpsc::Sender<AdminCommand>) {
+    let (rd, mut wr) = conn.into_split();
+
+    let mut line = String::new();
+    if let Err(err) = BufReader::new(rd).read_line(&mut line).await {
+        log::warn!("admin recv: {err}");
+        return;
+    }
+
+    let reply = match line.parse() {
+        Ok(request) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = AdminCommand { request, reply: reply_tx };
+
+            if tx.send(command).await.is_err() {
+                return;
+            }
+
+            match reply_rx.await {
+                Ok(reply) => reply,
+                Err(_) => return,
+            }
+        }
+        Err(err) => format!("error: {err}\n"),
+    };
+
+    if let Err(err) = wr.write_all(reply.as_bytes()).await {
+        log::warn!("admin send: {err}");
+    }
+}
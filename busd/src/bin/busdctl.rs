@@ -0,0 +1,69 @@
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use samsunghvac_client::transport;
+use structopt::StructOpt;
+
+/// Controls a running busd over its admin socket
+#[derive(StructOpt)]
+struct Opt {
+    /// Admin socket of the busd instance
+    #[structopt(long = "admin", default_value_os = transport::DEFAULT_ADMIN_SOCKET.as_os_str())]
+    admin: PathBuf,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// List connected peers
+    Peers,
+    /// Show forwarding latency stats
+    Stats,
+    /// Disconnect a client by id, as listed by peers
+    Disconnect { id: u32 },
+    /// Stop the daemon
+    Shutdown,
+}
+
+fn main() -> ExitCode {
+    let opt = Opt::from_args();
+    samsunghvac_common::log::init();
+
+    let admin = opt.admin;
+
+    let line = match opt.command {
+        Command::Peers => "peers".to_owned(),
+        Command::Stats => "stats".to_owned(),
+        Command::Disconnect { id } => format!("disconnect {id}"),
+        Command::Shutdown => "shutdown".to_owned(),
+    };
+
+    match send(&admin, &line) {
+        Ok(reply) if reply.starts_with("error:") => {
+            eprint!("{reply}");
+            ExitCode::FAILURE
+        }
+        Ok(reply) => {
+            print!("{reply}");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            log::error!("{}: {err}", admin.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn send(admin: &PathBuf, line: &str) -> Result<String, io::Error> {
+    let mut conn = UnixStream::connect(admin)?;
+    conn.write_all(format!("{line}\n").as_bytes())?;
+
+    // busd may exit before replying to a shutdown, in which case the
+    // connection is closed with no reply
+    let mut reply = String::new();
+    conn.read_to_string(&mut reply)?;
+    Ok(reply)
+}
@@ -1,72 +1,215 @@
+use std::fmt::{self, Display};
+use std::fs::{File, TryLockError};
 use std::io;
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::process::ExitCode;
+use std::sync::Arc;
 use std::task::{Context, Poll, ready};
 use std::time::Duration;
 
-use bytes::{Bytes, BytesMut};
-use derive_more::Display;
-use futures::{future, Stream, StreamExt};
+use bytes::Bytes;
+use futures::{future, Stream};
 use async_stream::stream;
-use samsunghvac_client::transport::{TransportReceiver, DEFAULT_SOCKET};
-use samsunghvac_protocol::frame::{FrameError, MAX_FRAME_SIZE};
-use samsunghvac_protocol::packet::{Packet, PacketError, SerializePacketError};
+use samsunghvac_client::transport::{self, TransportReceiver, DEFAULT_SOCKET};
+use samsunghvac_protocol::packet::{Packet, PacketMeta, SerializePacketError};
 use structopt::StructOpt;
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use tokio::net::UnixListener;
-use tokio::sync::mpsc::error::TrySendError;
-use tokio::sync::mpsc;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, Notify};
 use tokio_serial::{SerialPortBuilderExt, SerialStream};
 
+use admin::{AdminCommand, AdminRequest, DEFAULT_ADMIN_SOCKET};
+use switch::{Received, Switch};
+
+mod admin;
+mod switch;
+
 const BAUD_RATE: u32 = 9600;
+const SEND_QUEUE_SIZE: usize = 8;
+
+/// Reading from clients pauses while the bus send queue holds at least
+/// this many frames, so a stalled serial port pushes back on clients
+/// rather than overflowing the queue
+const BUS_QUEUE_HIGH_WATER: usize = 6;
+
+/// How often a standby instance checks whether the lock is free
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(StructOpt)]
 struct Opt {
     #[structopt(short = "l", long = "listen", default_value_os = DEFAULT_SOCKET.as_os_str())]
     pub socket: PathBuf,
-    pub port: String,
+    /// Socket for the admin interface used by busdctl
+    #[structopt(long = "admin", default_value_os = DEFAULT_ADMIN_SOCKET.as_os_str())]
+    pub admin: PathBuf,
+    /// Warn when forwarding a frame to peers takes longer than this many
+    /// milliseconds from when it was read
+    #[structopt(long = "latency-budget")]
+    pub latency_budget: Option<u64>,
+    /// Lock file shared with a standby instance. Whichever busd holds the
+    /// lock is active, the other waits to take over if the active one dies
+    #[structopt(long = "lock")]
+    pub lock: Option<PathBuf>,
+    /// Relay frames between stdin and stdout and the busd already
+    /// listening on the socket, exiting once either side closes. For use
+    /// over pipes or ssh, eg. `ssh host busd --stdio`
+    #[structopt(long = "stdio")]
+    pub stdio: bool,
+    #[structopt(required_unless = "stdio")]
+    pub port: Option<String>,
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), ExitCode> {
+fn main() -> ExitCode {
     let opt = Opt::from_args();
-    samsunghvac_common::log::init();
-
-    run(opt).await.map_err(|err| {
-        log::error!("{err}");
-        ExitCode::FAILURE
-    })
+    samsunghvac_app::run(run(opt))
 }
 
 async fn run(opt: Opt) -> Result<(), RunError> {
+    if opt.stdio {
+        return relay_stdio(&opt.socket).await;
+    }
+
+    let port = opt.port.as_ref().expect("port is required unless --stdio");
+
+    // held until we exit, the lock is released by the kernel however that
+    // happens:
+    let _lock = match &opt.lock {
+        Some(path) => {
+            let lock = acquire_lock(path).await
+                .map_err(|err| RunError::Lock(err, path.clone()))?;
+
+            // a previous active instance that died leaves its sockets
+            // behind. holding the lock, we know nobody is listening on them
+            remove_stale_socket(&opt.socket);
+            remove_stale_socket(&opt.admin);
+
+            Some(lock)
+        }
+        None => {
+            // without the lock, a socket is only stale if nobody answers
+            // on it. a live one is left for bind to report as in use
+            remove_dead_socket(&opt.socket);
+            remove_dead_socket(&opt.admin);
+            None
+        }
+    };
+
     let listen = UnixListener::bind(&opt.socket)
         .map_err(|err| RunError::Bind(err, opt.socket.clone()))?;
 
-    let port = open_serial_port(&opt.port)
-        .map_err(|err| RunError::OpenPort(err, opt.port.clone()))?;
+    // the admin interface can disconnect peers and stop the daemon, so
+    // keep it private to the user running busd:
+    let admin = UnixListener::bind(&opt.admin)
+        .map_err(|err| RunError::Bind(err, opt.admin.clone()))?;
+    std::fs::set_permissions(&opt.admin, std::fs::Permissions::from_mode(0o600))
+        .map_err(|err| RunError::Bind(err, opt.admin.clone()))?;
+
+    let port = open_serial_port(port)
+        .map_err(|err| RunError::OpenPort(err, port.clone()))?;
 
     let accept = start_accept(listen);
+    let admin = admin::start_admin(admin);
     let bus = Peer::new(PeerLabel::Bus, port);
-    multiplex(accept, bus).await;
+    let latency = LatencyStats::new(opt.latency_budget.map(Duration::from_millis));
+    multiplex(accept, admin, bus, latency).await;
+    log::info!("shutting down");
     Ok(())
 }
 
-fn multiplex(mut accept: mpsc::Receiver<Peer>, bus: Peer) -> impl Future<Output = ()> {
-    let mut peers = vec![bus];
+/// Connects stdin and stdout to the busd listening on `socket`, as a
+/// client like any other. The running busd owns the port and sockets, so
+/// nothing here opens or binds them
+async fn relay_stdio(socket: &Path) -> Result<(), RunError> {
+    let mut bus = UnixStream::connect(socket).await
+        .map_err(|err| RunError::Connect(err, socket.to_owned()))?;
+
+    let mut stdio = tokio::io::join(tokio::io::stdin(), tokio::io::stdout());
+
+    tokio::io::copy_bidirectional(&mut stdio, &mut bus).await
+        .map_err(RunError::Relay)?;
+
+    Ok(())
+}
+
+/// Takes the lock file, waiting as standby for as long as another instance
+/// holds it
+async fn acquire_lock(path: &Path) -> Result<File, io::Error> {
+    let file = File::options().create(true).truncate(false).write(true).open(path)?;
+    let mut standby = false;
+
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) => {
+                if !standby {
+                    log::info!("{} held by another instance, waiting as standby", path.display());
+                    standby = true;
+                }
+
+                tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+            }
+            Err(TryLockError::Error(err)) => return Err(err),
+        }
+    }
+
+    if standby {
+        log::warn!("took over as active instance");
+    }
+
+    Ok(file)
+}
+
+/// Removes a socket left behind by a busd that exited without cleaning up,
+/// recognised by connections to it being refused
+fn remove_dead_socket(path: &Path) {
+    match std::os::unix::net::UnixStream::connect(path) {
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => remove_stale_socket(path),
+        _ => {}
+    }
+}
+
+fn remove_stale_socket(path: &Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => log::info!("removed stale socket {}", path.display()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => log::warn!("removing stale socket {}: {err}", path.display()),
+    }
+}
+
+fn multiplex(
+    mut accept: mpsc::Receiver<Peer>,
+    mut admin: mpsc::Receiver<AdminCommand>,
+    bus: Peer,
+    mut latency: LatencyStats,
+) -> impl Future<Output = ()> {
+    let mut switch = Switch::new([bus]);
+    let mut backpressure = Backpressure::default();
 
     future::poll_fn(move |cx| {
         // handle accepting new clients first:
         match accept.poll_recv(cx) {
             Poll::Pending => {}
             Poll::Ready(None) => { return Poll::Ready(()); }
-            Poll::Ready(Some(peer)) => { peers.push(peer); }
+            Poll::Ready(Some(peer)) => {
+                log::info!("{}: connected", peer.label);
+                switch.add(peer);
+            }
+        }
+
+        // then admin commands:
+        while let Poll::Ready(Some(command)) = admin.poll_recv(cx) {
+            if on_admin(command, &mut switch, &latency) == AdminOutcome::Shutdown {
+                return Poll::Ready(());
+            }
         }
 
         // handle peer activity
         loop {
-            let (rx_idx, packet) = ready!(poll_peers(&mut peers, cx));
+            let bus_only = backpressure.poll(switch.peers(), cx);
+            let Received { from, packet, meta } = ready!(switch.poll_recv(bus_only, cx));
 
             let bytes = match serialize_frame(&packet) {
                 Ok(bytes) => bytes,
@@ -76,60 +219,187 @@ fn multiplex(mut accept: mpsc::Receiver<Peer>, bus: Peer) -> impl Future<Output
                 }
             };
 
-            let mut dead = vec![];
-
-            for (idx, peer) in peers.iter_mut().enumerate() {
-                // don't reflect packets back where they came:
-                if idx == rx_idx {
-                    continue;
-                }
+            // the peer's index is only good until forwarding, which can
+            // remove peers
+            let source = switch.peers()[from].label.clone();
+            switch.forward(from, &bytes);
 
-                match peer.tx.try_send(bytes.clone()) {
-                    Ok(()) => {}
-                    Err(TrySendError::Full(_)) => {}
-                    Err(TrySendError::Closed(_)) => {
-                        dead.push(idx);
-                    }
-                }
-            }
-
-            while let Some(idx) = dead.pop() {
-                peers.swap_remove(idx);
+            if let Some(received_at) = meta.received_at {
+                let elapsed = transport::monotonic_now().saturating_sub(received_at);
+                log::trace!("forwarded packet from transport {id:?} in {elapsed:?}",
+                    id = meta.transport_id);
+                latency.record(elapsed, &source);
             }
         }
     })
 }
 
-fn poll_peers(peers: &mut Vec<Peer>, cx: &mut Context<'_>) -> Poll<(usize, Box<Packet>)> {
-    'again: loop {
-        for (idx, peer) in peers.iter_mut().enumerate() {
-            match peer.rx.poll_next_unpin(cx) {
-                Poll::Pending => continue,
-                Poll::Ready(None) => {
-                    peers.swap_remove(idx);
-                    continue 'again;
-                }
-                Poll::Ready(Some(packet)) => {
-                    return Poll::Ready((idx, packet));
-                }
+/// Tracks whether client reads are paused because the bus send queue is
+/// backed up
+#[derive(Default)]
+struct Backpressure {
+    paused: bool,
+    /// number of times reads have been paused, reported in logs
+    pause_count: u64,
+    /// resolves when the bus send task next writes a frame
+    drained: Option<Pin<Box<dyn Future<Output = ()>>>>,
+}
+
+impl Backpressure {
+    /// Returns true if only the bus should be read from. While paused,
+    /// registers for a wakeup when the bus send queue drains.
+    fn poll(&mut self, peers: &[Peer], cx: &mut Context<'_>) -> bool {
+        let Some(bus) = peers.iter().find(|peer| matches!(peer.label, PeerLabel::Bus)) else {
+            return false;
+        };
+
+        let queued = bus.tx.max_capacity() - bus.tx.capacity();
+
+        if queued < BUS_QUEUE_HIGH_WATER {
+            if self.paused {
+                log::info!("bus send queue drained, resuming client reads");
             }
+
+            self.paused = false;
+            self.drained = None;
+            return false;
+        }
+
+        if !self.paused {
+            self.paused = true;
+            self.pause_count += 1;
+            log::warn!("bus send queue backed up ({queued} frames), pausing client reads (paused {} times)",
+                self.pause_count);
         }
 
-        return Poll::Pending;
+        let drained = self.drained.get_or_insert_with(|| {
+            let notify = bus.drained.clone();
+            Box::pin(async move { notify.notified().await })
+        });
+
+        // each write by the send task wakes us once to check the queue again
+        if drained.as_mut().poll(cx).is_ready() {
+            self.drained = None;
+            cx.waker().wake_by_ref();
+        }
+
+        true
+    }
+}
+
+/// Forwarding latency, from a frame being read to it being queued for
+/// every other peer. Exceeding the budget usually means the event loop
+/// stalled, which clients would otherwise only see as timeouts.
+struct LatencyStats {
+    budget: Option<Duration>,
+    /// number of frames forwarded over budget
+    over_budget: u64,
+    worst: Duration,
+}
+
+impl LatencyStats {
+    fn new(budget: Option<Duration>) -> Self {
+        LatencyStats { budget, over_budget: 0, worst: Duration::ZERO }
     }
+
+    fn record(&mut self, latency: Duration, source: &PeerLabel) {
+        self.worst = self.worst.max(latency);
+
+        if let Some(budget) = self.budget && latency > budget {
+            self.over_budget += 1;
+            log::warn!("{source}: forwarding took {latency:?}, over budget of {budget:?} ({} times)",
+                self.over_budget);
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum AdminOutcome {
+    Continue,
+    Shutdown,
+}
+
+fn on_admin(command: AdminCommand, switch: &mut Switch, latency: &LatencyStats) -> AdminOutcome {
+    let (reply, outcome) = match command.request {
+        AdminRequest::Peers => {
+            let reply = switch.peers().iter()
+                .map(|peer| format!("{}\n", peer.label))
+                .collect();
+
+            (reply, AdminOutcome::Continue)
+        }
+        AdminRequest::Stats => {
+            let mut reply = format!("latency_max_us {}\n", latency.worst.as_micros());
+
+            if let Some(budget) = latency.budget {
+                reply += &format!("latency_budget_us {}\n", budget.as_micros());
+                reply += &format!("latency_over_budget {}\n", latency.over_budget);
+            }
+
+            (reply, AdminOutcome::Continue)
+        }
+        AdminRequest::Disconnect { id } => {
+            if let Some(peer) = switch.disconnect(id) {
+                log::info!("{}: disconnected by admin", peer.label);
+                ("ok\n".to_owned(), AdminOutcome::Continue)
+            } else {
+                (format!("error: no client with id {id}\n"), AdminOutcome::Continue)
+            }
+        }
+        AdminRequest::Shutdown => {
+            log::info!("shutdown requested by admin");
+            ("ok\n".to_owned(), AdminOutcome::Shutdown)
+        }
+    };
+
+    let _: Result<_, _> = command.reply.send(reply);
+    outcome
 }
 
 struct Peer {
-    rx: Pin<Box<dyn Stream<Item = Box<Packet>> + Send>>,
+    rx: Pin<Box<dyn Stream<Item = (Box<Packet>, PacketMeta)> + Send>>,
     tx: mpsc::Sender<Bytes>,
+    /// notified each time the send task writes a frame
+    drained: Arc<Notify>,
+    label: PeerLabel,
 }
 
-#[derive(Display, Clone)]
+#[derive(Clone)]
 enum PeerLabel {
-    #[display("bus")]
     Bus,
-    #[display("client")]
-    Client,
+    /// Unix socket client, identified by connection number and the
+    /// credentials of the connecting process where available
+    Client { id: u32, pid: Option<i32>, uid: Option<u32> },
+}
+
+impl PeerLabel {
+    fn client(id: u32, stream: &UnixStream) -> Self {
+        let cred = stream.peer_cred().ok();
+
+        PeerLabel::Client {
+            id,
+            pid: cred.and_then(|cred| cred.pid()),
+            uid: cred.map(|cred| cred.uid()),
+        }
+    }
+}
+
+impl Display for PeerLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerLabel::Bus => write!(f, "bus"),
+            PeerLabel::Client { id, pid, uid } => {
+                write!(f, "client#{id}")?;
+                if let Some(pid) = pid {
+                    write!(f, " pid={pid}")?;
+                }
+                if let Some(uid) = uid {
+                    write!(f, " uid={uid}")?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 impl Peer {
@@ -141,18 +411,21 @@ impl Peer {
         let rx = Box::pin(recv_stream(rx, label.clone())) as Pin<_>;
 
         // spawn sender task, so that we can post messages without blocking
-        let (send_tx, send_rx) = mpsc::channel(8);
+        let (send_tx, send_rx) = mpsc::channel(SEND_QUEUE_SIZE);
+        let drained = Arc::new(Notify::new());
         let tx = Box::pin(tx) as Pin<Box<_>>;
-        tokio::spawn(send_task(tx, send_rx, label));
+        tokio::spawn(send_task(tx, send_rx, drained.clone(), label.clone()));
 
-        Peer { rx, tx: send_tx }
+        Peer { rx, tx: send_tx, drained, label }
     }
 }
 
-fn recv_stream(mut rx: TransportReceiver, label: PeerLabel) -> impl Stream<Item = Box<Packet>> {
+fn recv_stream(mut rx: TransportReceiver, label: PeerLabel)
+    -> impl Stream<Item = (Box<Packet>, PacketMeta)>
+{
     stream! {
         loop {
-            match rx.try_read().await {
+            match rx.try_read_with_meta().await {
                 Ok(Ok(packet)) => { yield packet; }
                 Ok(Err(err)) => { log::warn!("{label} recv: {err}"); }
                 Err(err) => {
@@ -167,6 +440,7 @@ fn recv_stream(mut rx: TransportReceiver, label: PeerLabel) -> impl Stream<Item
 async fn send_task(
     mut tx: Pin<Box<dyn AsyncWrite + Send>>,
     mut rx: mpsc::Receiver<Bytes>,
+    drained: Arc<Notify>,
     label: PeerLabel,
 ) {
     while let Some(bytes) = rx.recv().await {
@@ -174,6 +448,8 @@ async fn send_task(
             log::warn!("{label} send: {err}");
             break;
         }
+
+        drained.notify_one();
     }
 }
 
@@ -181,8 +457,14 @@ async fn send_task(
 enum RunError {
     #[error("binding {path}: {0}", path = .1.display())]
     Bind(#[source] io::Error, PathBuf),
+    #[error("locking {path}: {0}", path = .1.display())]
+    Lock(#[source] io::Error, PathBuf),
     #[error("opening bus port {1}: {0}")]
     OpenPort(#[source] serialport::Error, String),
+    #[error("connecting to {path}: {0}", path = .1.display())]
+    Connect(#[source] io::Error, PathBuf),
+    #[error("relaying stdio: {0}")]
+    Relay(#[source] io::Error),
 }
 
 fn start_accept(listen: UnixListener) -> mpsc::Receiver<Peer> {
@@ -192,6 +474,8 @@ fn start_accept(listen: UnixListener) -> mpsc::Receiver<Peer> {
 }
 
 async fn accept_task(listen: UnixListener, tx: mpsc::Sender<Peer>) {
+    let mut next_id = 0;
+
     loop {
         let (client, _) = match listen.accept().await {
             Ok(result) => result,
@@ -201,27 +485,18 @@ async fn accept_task(listen: UnixListener, tx: mpsc::Sender<Peer>) {
             }
         };
 
-        let label = PeerLabel::Client;
+        let label = PeerLabel::client(next_id, &client);
+        next_id += 1;
+
         let peer = Peer::new(label, client);
-        if let Err(_) = tx.send(peer).await {
+        if tx.send(peer).await.is_err() {
             break;
         }
     }
 }
 
 fn serialize_frame(packet: &Packet) -> Result<Bytes, SerializePacketError> {
-    let mut bytes = BytesMut::zeroed(MAX_FRAME_SIZE);
-    let n = packet.serialize_frame(&mut bytes)?;
-    bytes.truncate(n);
-    Ok(bytes.into())
-}
-
-#[derive(Error, Debug)]
-enum ReadPacketError {
-    #[error(transparent)]
-    Frame(#[from] FrameError),
-    #[error(transparent)]
-    Packet(#[from] PacketError),
+    Ok(packet.serialize_to_vec()?.into())
 }
 
 fn open_serial_port(path: &str) -> Result<SerialStream, tokio_serial::Error> {
@@ -0,0 +1,276 @@
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use samsunghvac_protocol::packet::{Packet, PacketMeta};
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::{Peer, PeerLabel};
+
+/// The set of connected peers, receiving from each in turn and forwarding
+/// frames to all but the peer they came from.
+///
+/// Peers are identified by their index into [`Switch::peers`], which is
+/// only valid until the next call that can remove one: `poll_recv`,
+/// `forward` or `disconnect`.
+pub struct Switch {
+    peers: Vec<Peer>,
+    /// index to start polling from, after the last peer to yield a packet
+    next: usize,
+}
+
+/// A packet received by the switch, along with the peer it came from
+pub struct Received {
+    pub from: usize,
+    pub packet: Box<Packet>,
+    pub meta: PacketMeta,
+}
+
+impl Switch {
+    pub fn new(peers: impl IntoIterator<Item = Peer>) -> Self {
+        Switch { peers: peers.into_iter().collect(), next: 0 }
+    }
+
+    pub fn peers(&self) -> &[Peer] {
+        &self.peers
+    }
+
+    pub fn add(&mut self, peer: Peer) {
+        self.peers.push(peer);
+    }
+
+    /// Removes the client with connection number `id`. Dropping the peer
+    /// closes its receive stream and ends its send task, closing the socket
+    pub fn disconnect(&mut self, id: u32) -> Option<Peer> {
+        let idx = self.peers.iter().position(|peer| {
+            matches!(peer.label, PeerLabel::Client { id: peer_id, .. } if peer_id == id)
+        })?;
+
+        Some(self.peers.swap_remove(idx))
+    }
+
+    /// Polls peers round-robin, starting after the last peer to yield a
+    /// packet, so that one chatty peer can't starve the others. Peers whose
+    /// receive stream has ended are removed. With `bus_only`, client peers
+    /// are left unpolled.
+    pub fn poll_recv(&mut self, bus_only: bool, cx: &mut Context<'_>) -> Poll<Received> {
+        'again: loop {
+            let count = self.peers.len();
+
+            for offset in 0..count {
+                let idx = (self.next + offset) % count;
+                let peer = &mut self.peers[idx];
+
+                if bus_only && !matches!(peer.label, PeerLabel::Bus) {
+                    continue;
+                }
+
+                match peer.rx.poll_next_unpin(cx) {
+                    Poll::Pending => continue,
+                    Poll::Ready(None) => {
+                        log::info!("{}: disconnected, receive stream ended", peer.label);
+                        // removing reorders the peers, so start the round
+                        // over rather than carry on with stale indices
+                        self.peers.swap_remove(idx);
+                        continue 'again;
+                    }
+                    Poll::Ready(Some((packet, meta))) => {
+                        self.next = idx + 1;
+                        return Poll::Ready(Received { from: idx, packet, meta });
+                    }
+                }
+            }
+
+            return Poll::Pending;
+        }
+    }
+
+    /// Queues a frame for every peer but `from`, where it came from.
+    /// Peers with a full queue miss the frame, peers whose send task has
+    /// ended are removed
+    pub fn forward(&mut self, from: usize, bytes: &Bytes) {
+        let mut dead = vec![];
+
+        for (idx, peer) in self.peers.iter_mut().enumerate() {
+            // don't reflect packets back where they came:
+            if idx == from {
+                continue;
+            }
+
+            match peer.tx.try_send(bytes.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    log::debug!("{}: send queue full, dropping packet", peer.label);
+                }
+                Err(TrySendError::Closed(_)) => {
+                    log::info!("{}: disconnected, send task ended", peer.label);
+                    dead.push(idx);
+                }
+            }
+        }
+
+        // highest index first, so swap_remove only moves peers that are
+        // being kept into the slots of ones already removed
+        while let Some(idx) = dead.pop() {
+            self.peers.swap_remove(idx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::Waker;
+
+    use futures::{stream, Stream};
+    use samsunghvac_protocol::packet::{addresses, Address, Data, DataType, MessagesVec, PacketInfo, PacketType};
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    fn packet(source: u8) -> (Box<Packet>, PacketMeta) {
+        let packet = Packet {
+            source: Address { address: source, ..addresses::INDOOR },
+            destination: addresses::BROADCAST,
+            packet_info: PacketInfo::default(),
+            packet_type: PacketType::Normal,
+            data_type: DataType::Notification,
+            packet_number: 0,
+            data: Data::Messages(MessagesVec::new()),
+        };
+
+        (Box::new(packet), PacketMeta::default())
+    }
+
+    fn peer(rx: impl Stream<Item = (Box<Packet>, PacketMeta)> + Send + 'static) -> Peer {
+        let (tx, _) = mpsc::channel(1);
+        Peer { rx: Box::pin(rx), tx, drained: Default::default(), label: PeerLabel::Bus }
+    }
+
+    /// A client peer that never sends, and the receiving end of its queue
+    fn listener(id: u32) -> (Peer, mpsc::Receiver<Bytes>) {
+        let (tx, rx) = mpsc::channel(4);
+        let label = PeerLabel::Client { id, pid: None, uid: None };
+        let peer = Peer { rx: Box::pin(stream::pending()), tx, drained: Default::default(), label };
+        (peer, rx)
+    }
+
+    fn client_id(peer: &Peer) -> Option<u32> {
+        match peer.label {
+            PeerLabel::Client { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+
+    fn poll_source(switch: &mut Switch) -> u8 {
+        let mut cx = Context::from_waker(Waker::noop());
+        match switch.poll_recv(false, &mut cx) {
+            Poll::Ready(received) => received.packet.source.address,
+            Poll::Pending => panic!("expected packet"),
+        }
+    }
+
+    #[test]
+    fn chatty_peer_does_not_starve_others() {
+        let chatty = peer(stream::repeat_with(|| packet(0)));
+        let quiet = peer(stream::iter([packet(1), packet(1)]).chain(stream::pending()));
+
+        let mut switch = Switch::new([chatty, quiet]);
+
+        let sources = (0..4)
+            .map(|_| poll_source(&mut switch))
+            .collect::<Vec<_>>();
+
+        assert_eq!(sources, [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn ended_peer_is_removed() {
+        let ended = peer(stream::empty());
+        let live = peer(stream::repeat_with(|| packet(1)));
+
+        let mut switch = Switch::new([ended, live]);
+
+        assert_eq!(poll_source(&mut switch), 1);
+        assert_eq!(switch.peers().len(), 1);
+        assert_eq!(poll_source(&mut switch), 1);
+    }
+
+    #[test]
+    fn ended_peers_in_a_row_are_all_removed() {
+        let mut switch = Switch::new([
+            peer(stream::empty()),
+            peer(stream::empty()),
+            peer(stream::iter([packet(2)]).chain(stream::pending())),
+            peer(stream::empty()),
+        ]);
+
+        assert_eq!(poll_source(&mut switch), 2);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(switch.poll_recv(false, &mut cx).is_pending());
+        assert_eq!(switch.peers().len(), 1);
+    }
+
+    #[test]
+    fn bus_only_skips_clients() {
+        let (mut client, _rx) = listener(0);
+        client.rx = Box::pin(stream::repeat_with(|| packet(0)));
+        let bus = peer(stream::iter([packet(1)]).chain(stream::pending()));
+
+        let mut switch = Switch::new([client, bus]);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let Poll::Ready(received) = switch.poll_recv(true, &mut cx) else {
+            panic!("expected packet");
+        };
+        assert_eq!(received.packet.source.address, 1);
+        assert!(switch.poll_recv(true, &mut cx).is_pending());
+    }
+
+    #[test]
+    fn forward_does_not_reflect() {
+        let (a, mut a_rx) = listener(0);
+        let (b, mut b_rx) = listener(1);
+        let (c, mut c_rx) = listener(2);
+
+        let mut switch = Switch::new([a, b, c]);
+        switch.forward(1, &Bytes::from_static(b"frame"));
+
+        assert_eq!(a_rx.try_recv().unwrap(), "frame");
+        assert!(b_rx.try_recv().is_err());
+        assert_eq!(c_rx.try_recv().unwrap(), "frame");
+    }
+
+    #[test]
+    fn forward_removes_dead_peers() {
+        let (a, a_rx) = listener(0);
+        let (b, mut b_rx) = listener(1);
+        let (c, c_rx) = listener(2);
+        let (d, mut d_rx) = listener(3);
+
+        drop(a_rx);
+        drop(c_rx);
+
+        let mut switch = Switch::new([a, b, c, d]);
+        switch.forward(usize::MAX, &Bytes::from_static(b"frame"));
+
+        let mut ids = switch.peers().iter().filter_map(client_id).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, [1, 3]);
+
+        assert_eq!(b_rx.try_recv().unwrap(), "frame");
+        assert_eq!(d_rx.try_recv().unwrap(), "frame");
+    }
+
+    #[test]
+    fn disconnect_removes_client() {
+        let (a, _a_rx) = listener(0);
+        let (b, _b_rx) = listener(1);
+
+        let mut switch = Switch::new([a, b]);
+
+        assert!(switch.disconnect(5).is_none());
+        assert_eq!(switch.disconnect(0).as_ref().and_then(client_id), Some(0));
+        assert_eq!(switch.peers().iter().filter_map(client_id).collect::<Vec<_>>(), [1]);
+    }
+}
@@ -0,0 +1,112 @@
+//! Merges requests to the same address made within a short window into a
+//! single Request packet
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use samsunghvac_protocol::packet::{Address, Message};
+use tokio::sync::oneshot;
+use tokio::task;
+
+use crate::{Error, Shared};
+
+/// Most messages merged into one packet. A nack fails every request in
+/// a batch, so batches are kept to about what one UI interaction sends
+const MAX_BATCH_MESSAGES: usize = 10;
+
+#[derive(Default)]
+pub(crate) struct Batches {
+    pending: RefCell<HashMap<Address, Batch>>,
+    next_serial: Cell<u64>,
+}
+
+struct Batch {
+    /// tells the timer for this batch apart from one for a batch that
+    /// replaced it after being flushed early
+    serial: u64,
+    messages: Vec<Message>,
+    waiters: Vec<oneshot::Sender<Result<(), Error>>>,
+}
+
+/// Joins the pending batch for `address`, or starts a new one, and waits
+/// for the batch to be sent. The messages are sent even if this future
+/// is dropped
+pub(crate) async fn request(shared: &Rc<Shared>, address: Address, messages: &[Message])
+    -> Result<(), Error>
+{
+    let (tx, rx) = oneshot::channel();
+
+    let join = {
+        let mut pending = shared.batches.pending.borrow_mut();
+
+        match pending.get_mut(&address) {
+            Some(batch) if batch.messages.len() + messages.len() <= MAX_BATCH_MESSAGES => {
+                batch.messages.extend_from_slice(messages);
+                batch.waiters.push(tx);
+                None
+            }
+            _ => Some(tx),
+        }
+    };
+
+    if let Some(tx) = join {
+        // this request doesn't fit in the pending batch, if there is one,
+        // so send that now and start another
+        if let Some(full) = shared.batches.pending.borrow_mut().remove(&address) {
+            task::spawn_local(send_batch(shared.clone(), address, full));
+        }
+
+        let serial = shared.batches.next_serial.get();
+        shared.batches.next_serial.set(serial + 1);
+
+        let batch = Batch { serial, messages: messages.to_vec(), waiters: vec![tx] };
+        shared.batches.pending.borrow_mut().insert(address, batch);
+
+        let window = shared.options.batch_window;
+        task::spawn_local(flush_after(shared.clone(), address, serial, window));
+    }
+
+    rx.await.map_err(|_| Error::LostTransport)?
+}
+
+async fn flush_after(shared: Rc<Shared>, address: Address, serial: u64, window: Duration) {
+    tokio::time::sleep(window).await;
+
+    let batch = {
+        let mut pending = shared.batches.pending.borrow_mut();
+
+        match pending.get(&address) {
+            Some(batch) if batch.serial == serial => pending.remove(&address),
+            // already flushed early
+            _ => None,
+        }
+    };
+
+    if let Some(batch) = batch {
+        send_batch(shared, address, batch).await;
+    }
+}
+
+async fn send_batch(shared: Rc<Shared>, address: Address, batch: Batch) {
+    if batch.waiters.len() > 1 {
+        log::debug!("merged {} requests to {address} into one packet", batch.waiters.len());
+    }
+
+    let result = shared.request(address, &batch.messages).await;
+    let mut waiters = batch.waiters;
+
+    if waiters.len() == 1 {
+        let _: Result<_, _> = waiters.remove(0).send(result);
+        return;
+    }
+
+    // requests merged into one packet share its result
+    let result = result.map_err(Arc::new);
+
+    for waiter in waiters {
+        let _: Result<_, _> = waiter.send(result.clone().map_err(Error::Batched));
+    }
+}
@@ -0,0 +1,278 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+use futures::{Stream, StreamExt};
+use samsunghvac_protocol::frame::{FrameBuffer, FrameError, FrameParser, DEFAULT_FRAME_TIMEOUT};
+use samsunghvac_protocol::packet::Packet;
+use tokio::io::AsyncRead;
+use tokio::time::{Instant, Sleep};
+use tokio_util::codec::{Decoder, Encoder, FramedRead};
+
+use crate::transport::{ReadPacketError, SendPacketError};
+
+/// Codec for NASA frames, for use with [`tokio_util::codec::Framed`]
+///
+/// Decoded items are nested results in the same way as
+/// [`TransportReceiver::try_read`](crate::transport::TransportReceiver::try_read):
+/// a corrupt frame or unparseable packet is yielded as an inner error
+/// without ending the stream, while the outer error is reserved for i/o.
+///
+/// A decoder can't tell how long the input was quiet, so stalled frames
+/// are only abandoned when [`NasaCodec::stalled`] is called, as
+/// [`FrameStream`] does while waiting on input.
+pub struct NasaCodec {
+    parser: FrameParser,
+    frame_timeout: Option<Duration>,
+    /// when bytes were last handed to the decoder
+    last_input: Option<Instant>,
+}
+
+impl Default for NasaCodec {
+    fn default() -> Self {
+        NasaCodec {
+            parser: FrameParser::new(),
+            frame_timeout: Some(DEFAULT_FRAME_TIMEOUT),
+            last_input: None,
+        }
+    }
+}
+
+impl NasaCodec {
+    pub fn new() -> Self {
+        NasaCodec::default()
+    }
+
+    /// How long the input may be quiet mid-frame before the frame is
+    /// abandoned, [`DEFAULT_FRAME_TIMEOUT`] by default. `None` waits
+    /// indefinitely
+    pub fn frame_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.frame_timeout = timeout;
+        self
+    }
+
+    /// When the input will have been quiet for the frame timeout, if
+    /// there's been input since the last check
+    pub fn stall_deadline(&self) -> Option<Instant> {
+        Some(self.last_input? + self.frame_timeout?)
+    }
+
+    /// Abandons a partial frame if no bytes have been decoded for the frame
+    /// timeout as of `now`. Only meaningful while the caller is waiting on
+    /// input, otherwise bytes that arrived in the meantime are still unread
+    pub fn stalled(&mut self, now: Instant) -> Option<ReadPacketError> {
+        if self.stall_deadline()? > now {
+            return None;
+        }
+
+        self.last_input = None;
+        self.parser.feed_timeout().err().map(Into::into)
+    }
+}
+
+impl Decoder for NasaCodec {
+    type Item = Result<Box<Packet>, ReadPacketError>;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, io::Error> {
+        // frames recovered from the bytes of a bad frame come first
+        if let Some(item) = decoded(self.parser.resume()) {
+            return Ok(Some(item));
+        }
+
+        if src.is_empty() {
+            return Ok(None);
+        }
+
+        self.last_input = Some(Instant::now());
+
+        let mut consumed = 0;
+        let mut item = None;
+
+        for byte in src.iter() {
+            consumed += 1;
+            item = decoded(self.parser.feed(*byte));
+
+            if item.is_some() {
+                break;
+            }
+        }
+
+        // bytes fed to the parser are held in its own buffer, so everything
+        // up to this point is consumed whether or not a frame completed
+        src.advance(consumed);
+        Ok(item)
+    }
+}
+
+/// [`FramedRead`] over a [`NasaCodec`], abandoning a partial frame once
+/// the input has been quiet for the codec's frame timeout. Time spent
+/// unpolled doesn't count, as bytes may have arrived in the meantime.
+pub struct FrameStream<R> {
+    framed: FramedRead<R, NasaCodec>,
+    /// created on first use, so that streams can be made outside a runtime
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R: AsyncRead + Unpin> FrameStream<R> {
+    pub fn new(input: R, codec: NasaCodec) -> Self {
+        FrameStream {
+            framed: FramedRead::new(input, codec),
+            timer: None,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for FrameStream<R> {
+    type Item = Result<Result<Box<Packet>, ReadPacketError>, io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Poll::Ready(item) = self.framed.poll_next_unpin(cx) {
+                return Poll::Ready(item);
+            }
+
+            // input is pending, so quiet from here on counts
+            let Some(deadline) = self.framed.decoder().stall_deadline() else {
+                return Poll::Pending;
+            };
+
+            let timer = self.timer.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+            timer.as_mut().reset(deadline);
+
+            if timer.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            if let Some(err) = self.framed.decoder_mut().stalled(Instant::now()) {
+                return Poll::Ready(Some(Ok(Err(err))));
+            }
+        }
+    }
+}
+
+fn decoded(result: Result<Option<&FrameBuffer>, FrameError>)
+    -> Option<Result<Box<Packet>, ReadPacketError>>
+{
+    match result {
+        Ok(None) => None,
+        Ok(Some(frame)) => Some(Packet::parse(frame).map(Box::new).map_err(Into::into)),
+        Err(err) => Some(Err(err.into())),
+    }
+}
+
+impl Encoder<&Packet> for NasaCodec {
+    type Error = SendPacketError;
+
+    fn encode(&mut self, packet: &Packet, dst: &mut BytesMut) -> Result<(), SendPacketError> {
+        let start = dst.len();
+        dst.resize(start + packet.serialized_len(), 0);
+
+        match packet.serialize_frame(&mut dst[start..]) {
+            Ok(n) => {
+                dst.truncate(start + n);
+                Ok(())
+            }
+            Err(err) => {
+                dst.truncate(start);
+                Err(err.into())
+            }
+        }
+    }
+}
+
+impl Encoder<Packet> for NasaCodec {
+    type Error = SendPacketError;
+
+    fn encode(&mut self, packet: Packet, dst: &mut BytesMut) -> Result<(), SendPacketError> {
+        self.encode(&packet, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use samsunghvac_protocol::frame::FrameError;
+    use samsunghvac_protocol::packet::{addresses, Data, DataType, MessagesVec, PacketInfo, PacketType};
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    fn frame(packet_number: u8) -> Vec<u8> {
+        let packet = Packet {
+            source: addresses::INDOOR,
+            destination: addresses::BROADCAST,
+            packet_info: PacketInfo::default(),
+            packet_type: PacketType::Normal,
+            data_type: DataType::Notification,
+            packet_number,
+            data: Data::Messages(MessagesVec::new()),
+        };
+
+        packet.serialize_to_vec().unwrap()
+    }
+
+    fn is_stalled(err: &ReadPacketError) -> bool {
+        matches!(err, ReadPacketError::Frame(FrameError::Stalled { .. }))
+    }
+
+    #[test]
+    fn stalled_only_after_timeout() {
+        let mut codec = NasaCodec::new();
+        let frame = frame(1);
+        let (head, tail) = frame.split_at(frame.len() / 2);
+
+        assert!(codec.decode(&mut BytesMut::from(head)).unwrap().is_none());
+
+        let now = Instant::now();
+        assert!(codec.stalled(now).is_none());
+        assert!(codec.stalled(now + DEFAULT_FRAME_TIMEOUT * 2).is_some_and(|err| is_stalled(&err)));
+
+        // the rest of the frame is now just noise before the next one
+        let mut src = BytesMut::from(tail);
+        src.extend_from_slice(&self::frame(2));
+        let packet = codec.decode(&mut src).unwrap().unwrap().unwrap();
+        assert_eq!(packet.packet_number, 2);
+    }
+
+    #[test]
+    fn slow_decode_calls_keep_frame() {
+        let mut codec = NasaCodec::new();
+        let frame = frame(1);
+        let (head, tail) = frame.split_at(frame.len() / 2);
+
+        assert!(codec.decode(&mut BytesMut::from(head)).unwrap().is_none());
+        std::thread::sleep(DEFAULT_FRAME_TIMEOUT * 2);
+
+        let packet = codec.decode(&mut BytesMut::from(tail)).unwrap().unwrap().unwrap();
+        assert_eq!(packet.packet_number, 1);
+    }
+
+    #[test]
+    fn no_timeout_never_stalls() {
+        let mut codec = NasaCodec::new().frame_timeout(None);
+        let frame = frame(1);
+
+        assert!(codec.decode(&mut BytesMut::from(&frame[..4])).unwrap().is_none());
+        assert!(codec.stall_deadline().is_none());
+        assert!(codec.stalled(Instant::now() + Duration::from_secs(60)).is_none());
+    }
+
+    #[tokio::test]
+    async fn stream_abandons_frame_when_input_goes_quiet() {
+        let (mut tx, rx) = tokio::io::duplex(1024);
+        let codec = NasaCodec::new().frame_timeout(Some(Duration::from_millis(10)));
+        let mut stream = FrameStream::new(rx, codec);
+
+        let frame = frame(1);
+        tx.write_all(&frame[..frame.len() / 2]).await.unwrap();
+
+        let err = stream.next().await.unwrap().unwrap().unwrap_err();
+        assert!(is_stalled(&err));
+
+        tx.write_all(&self::frame(2)).await.unwrap();
+        let packet = stream.next().await.unwrap().unwrap().unwrap();
+        assert_eq!(packet.packet_number, 2);
+    }
+}
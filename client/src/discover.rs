@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use samsunghvac_protocol::message::{self, IsMessage};
+use samsunghvac_protocol::packet::addresses::DeviceClass;
+use samsunghvac_protocol::packet::Address;
+
+use crate::Client;
+
+/// A device found on the bus by [`Client::discover`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredDevice {
+    pub address: Address,
+    pub class: DeviceClass,
+    /// model code, for units that report one
+    pub model: Option<u16>,
+    /// rated capacity, for units that report one
+    pub capacity: Option<u16>,
+}
+
+impl Client {
+    /// Listens to the bus for `window`, then returns every device seen
+    /// sending anything since the client started. Indoor units are also
+    /// asked for their model and capacity; units that don't reply are still
+    /// returned, without them.
+    pub async fn discover(&self, window: Duration) -> Vec<DiscoveredDevice> {
+        tokio::time::sleep(window).await;
+
+        let mut addresses = self.shared.seen.borrow().iter().copied().collect::<Vec<_>>();
+        addresses.sort_by_key(|address| address.to_u32());
+
+        let mut devices = Vec::new();
+
+        for address in addresses {
+            let class = address.device_class();
+            let mut device = DiscoveredDevice { address, class, model: None, capacity: None };
+
+            if class == DeviceClass::Indoor {
+                let ids = [message::ModelInformation::ID, message::CapacityAbsolute::ID];
+
+                match self.read(address, &ids).await {
+                    Ok(data) => {
+                        device.model = data.get::<message::ModelInformation>();
+                        device.capacity = data.get::<message::CapacityAbsolute>();
+                    }
+                    Err(err) => {
+                        log::debug!("reading identity of {address}: {err}");
+                    }
+                }
+            }
+
+            devices.push(device);
+        }
+
+        devices
+    }
+}
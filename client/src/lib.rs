@@ -1,85 +1,267 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::rc::Rc;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
 use std::time::Duration;
 
-use samsunghvac_protocol::packet::{u2, Address, Data, DataType, Message, MessageKind, MessageId, Packet, PacketInfo, PacketType, Value};
+use samsunghvac_protocol::message::IsMessage;
+use samsunghvac_protocol::packet::addresses::{self, DeviceClass};
+use samsunghvac_protocol::packet::{self, u2, MAX_MESSAGE_COUNT, Address, Data, DataType, Message, MessageKind, MessageId, MessagesVec, Packet, PacketInfo, PacketMeta, PacketType, Structure, StructureData, Value};
+use futures::Stream;
 use thiserror::Error;
-use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tokio::sync::broadcast;
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::Instant;
 use tokio::task;
-use transport::{OpenError, SendPacketError, TransportOpt, TransportReceiver, TransportSender};
+use transport::{AsyncTransport, OpenError, ReadPacketError, SendPacketError, TransportOpt, TransportReceiver, TransportSender};
 
+mod batch;
+pub mod codec;
+pub mod discover;
+mod nack;
+mod options;
+mod recent;
+pub mod pcap;
+pub mod poll;
+pub mod respond;
+pub mod retry;
+mod rng;
+pub mod threaded;
 pub mod transport;
 pub mod message;
+mod metrics;
+pub mod watch;
 
-use message::MessageSet;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+pub use metrics::ClientMetrics;
+pub use nack::Nack;
+pub use options::ClientOptions;
+pub use recent::RecentNotification;
+
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
 
-const LOCAL_ADDRESS: Address = Address { class: 0x80, channel: 0x10, address: 0x10 };
-const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Most attributes read in one packet. Units don't accept reads anywhere
+/// near as long as would fit, so longer reads are split
+const MAX_READ_ATTRS: usize = 10;
+
+/// Packets buffered for each [`Client::packets`] stream before a slow
+/// reader starts missing them
+const PACKET_TAP_CAPACITY: usize = 256;
+
+use batch::Batches;
+use recent::RecentBuffer;
+use message::MessageSet;
+use metrics::MetricsCell;
+use respond::Responder;
+use retry::RetryPolicy;
+use watch::{Watch, WatchRegistry};
 
 pub struct Client {
     shared: Rc<Shared>,
     reader: task::JoinHandle<()>,
-    packet_number: AtomicU8,
 }
 
 pub trait Callbacks {
     fn on_notification(&self, sender: Address, data: &MessageSet);
+
+    /// Called the first time any packet is seen from an address
+    fn on_new_device(&self, _address: Address, _class: DeviceClass) {}
+
+    /// Called with the error the transport was lost to, whether or not
+    /// the client goes on to reconnect
+    fn on_disconnected(&self, _reason: &io::Error) {}
+
+    /// Called once the transport has been reopened after being lost
+    fn on_connected(&self) {}
+
+    /// Called for each frame or packet received that fails to parse
+    fn on_parse_error(&self, _error: &ReadPacketError) {}
+
+    /// Called with every packet received, before the client handles it
+    fn on_raw_packet(&self, _packet: &Packet, _meta: &PacketMeta) {}
 }
 
 struct Shared {
-    address: Address,
+    options: ClientOptions,
     writer: AsyncMutex<TransportSender>,
-    waiting: RefCell<HashMap<u8, oneshot::Sender<Box<Packet>>>>,
+    /// requests awaiting replies, by destination and packet number
+    waiting: RefCell<HashMap<(Address, u8), Waiting>>,
     callbacks: Box<dyn Callbacks>,
+    watches: WatchRegistry,
+    auto_watch: Cell<bool>,
+    responder: RefCell<Option<Rc<dyn Responder>>>,
+    /// source addresses we've seen packets from
+    seen: RefCell<HashSet<Address>>,
+    /// replay buffer of notifications, for [`Client::recent`]
+    recent: RecentBuffer,
+    /// traffic counters, for [`Client::metrics`]
+    metrics: MetricsCell,
+    /// copies of every packet received, for [`Client::packets`]
+    tap: broadcast::Sender<Box<Packet>>,
+    /// requests waiting to be merged into one packet, by destination
+    batches: Batches,
+    /// serialises requests to each destination, holding when the last
+    /// one finished
+    queues: RefCell<HashMap<Address, Rc<AsyncMutex<Option<Instant>>>>>,
+    packet_number: AtomicU8,
+    /// identifies each request in logs, unlike packet numbers which wrap
+    request_id: AtomicU64,
+}
+
+type Reply = (Box<Packet>, PacketMeta);
+
+struct Waiting {
+    request_id: u64,
+    data_type: DataType,
+    started_at: Instant,
+    retries: Cell<u32>,
+    reply_tx: oneshot::Sender<Reply>,
 }
 
 impl Client {
-    pub async fn connect(opt: &TransportOpt, callbacks: impl Callbacks + 'static)
+    pub async fn connect(opt: &TransportOpt, options: ClientOptions, callbacks: impl Callbacks + 'static)
         -> Result<Self, transport::OpenError>
     {
-        Self::connect_boxed(opt, Box::new(callbacks) as Box<_>).await
+        Self::connect_boxed(opt, options, Box::new(callbacks) as Box<_>).await
     }
 
-    pub async fn connect_boxed(opt: &TransportOpt, callbacks: Box<dyn Callbacks>)
+    pub async fn connect_boxed(opt: &TransportOpt, options: ClientOptions, callbacks: Box<dyn Callbacks>)
         -> Result<Self, OpenError>
     {
-        let (reader, writer) = transport::open(opt).await?;
+        let transport = transport::open(opt).await?;
+        let reconnect = options.reconnect.then(|| opt.clone());
+        Ok(Self::start(transport, options, callbacks, reconnect))
+    }
+
+    /// Creates a client over an already open transport. The client has no
+    /// way to reopen it, so won't reconnect if it's lost
+    pub fn from_transport(transport: AsyncTransport, options: ClientOptions, callbacks: Box<dyn Callbacks>)
+        -> Self
+    {
+        Self::start(transport, options, callbacks, None)
+    }
+
+    fn start(
+        transport: AsyncTransport,
+        options: ClientOptions,
+        callbacks: Box<dyn Callbacks>,
+        reconnect: Option<TransportOpt>,
+    ) -> Self {
+        let (reader, writer) = transport;
+        let recent = RecentBuffer::new(options.recent_notifications);
 
         let shared = Rc::new(Shared {
-            address: LOCAL_ADDRESS,
+            options,
             writer: AsyncMutex::new(writer),
             waiting: Default::default(),
             callbacks,
+            watches: WatchRegistry::new(),
+            auto_watch: Cell::new(false),
+            responder: Default::default(),
+            seen: Default::default(),
+            recent,
+            metrics: Default::default(),
+            tap: broadcast::Sender::new(PACKET_TAP_CAPACITY),
+            batches: Default::default(),
+            queues: Default::default(),
+            packet_number: AtomicU8::default(),
+            request_id: AtomicU64::default(),
         });
 
         let reader = tokio::task::spawn_local(
-            reader_task(shared.clone(), reader));
+            reader_task(shared.clone(), reader, reconnect));
 
-        Ok(Client {
-            shared,
-            reader,
-            packet_number: AtomicU8::default(),
+        Client { shared, reader }
+    }
+
+    /// Attributes watched by this client, kept fresh from notifications
+    pub fn watches(&self) -> &WatchRegistry {
+        &self.shared.watches
+    }
+
+    /// Registers attributes in the watch registry. With `fetch_initial`,
+    /// the attributes are also read straight away, so the registry has
+    /// values for them without waiting for the next notification
+    pub async fn watch_attrs(&self, address: Address, attrs: &[MessageId], fetch_initial: bool)
+        -> Result<(), Error>
+    {
+        for attr in attrs {
+            self.shared.watches.watch(address, *attr);
+        }
+
+        if fetch_initial {
+            self.read(address, attrs).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Watches a message from `address`, returning a subscription that
+    /// yields its value each time a notification or read changes it
+    pub fn watch<M: IsMessage>(&self, address: Address) -> Watch<M> {
+        self.shared.watches.subscribe::<M>(address)
+    }
+
+    /// Stream of every packet the client receives, whoever it's addressed
+    /// to, as it arrives. A stream that falls too far behind skips the
+    /// packets it missed. Ends when the client is dropped
+    pub fn packets(&self) -> impl Stream<Item = Box<Packet>> + use<> {
+        let rx = self.shared.tap.subscribe();
+
+        futures::stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(packet) => return Some((packet, rx)),
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        log::warn!("packet stream lagging, skipped {count} packets");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
         })
     }
 
-    fn next_packet_number(&self) -> u8 {
-        self.packet_number.fetch_add(1, Ordering::SeqCst)
+    /// When enabled, every attribute read with [`Client::read`] is
+    /// registered in the watch registry
+    pub fn set_auto_watch(&self, enabled: bool) {
+        self.shared.auto_watch.set(enabled);
     }
 
-    pub async fn read(&self, address: Address, attrs: &[MessageId]) -> Result<MessageSet, Error> {
+    pub async fn read(&self, address: Address, attrs: &[MessageId]) -> Result<MessageSet<'_>, Error> {
         let queries = attrs.iter()
             .filter_map(|attr| query(*attr))
             .collect::<Vec<_>>();
 
-        let reply = self.send(address, DataType::Read, &queries).await?;
-        let reply = expect_reply(reply, DataType::Response)?;
+        let mut replies = Vec::new();
 
-        let messages = match reply.data {
-            Data::Messages(msgs) => MessageSet::from_vec(msgs.to_vec()),
-            Data::Structure(_) => MessageSet::new(&[]),
-        };
+        // long reads are split over several packets, the replies merged
+        for chunk in queries.chunks(MAX_READ_ATTRS) {
+            let reply = self.shared.send(address, DataType::Read, chunk).await?;
+            let reply = expect_reply(reply, DataType::Response)?;
+
+            if let Data::Structure(structure) = reply.data {
+                return Err(Error::StructureReply(Box::new(structure)));
+            }
+
+            replies.extend_from_slice(reply.data.messages());
+        }
+
+        let messages = MessageSet::from_vec(replies);
+
+        if self.shared.auto_watch.get() {
+            for attr in attrs {
+                self.shared.watches.watch(address, *attr);
+            }
+        }
+
+        self.shared.watches.update(address, messages.messages());
 
         return Ok(messages);
 
@@ -97,39 +279,283 @@ impl Client {
         }
     }
 
+    /// Reads a structure message. Structures can't be read alongside
+    /// other messages, so are read one at a time
+    pub async fn read_structure(&self, address: Address, id: MessageId) -> Result<Structure, Error> {
+        let query = Data::Structure(Structure { number: id, data: StructureData::new() });
+
+        let reply = self.shared.send_data(address, DataType::Read, query).await?;
+        let reply = expect_reply(reply, DataType::Response)?;
+
+        match reply.data {
+            Data::Structure(structure) if structure.number == id => Ok(structure),
+            _ => Err(Error::MissingMessage(id)),
+        }
+    }
+
+    /// Requests new values for messages. With a batch window set in
+    /// [`ClientOptions`], requests to the same address within the window
+    /// are merged into one packet. Requests to a broadcast address are
+    /// sent once without waiting for a reply, as with
+    /// [`Client::send_broadcast`]
     pub async fn request(&self, address: Address, messages: &[Message]) -> Result<(), Error> {
+        if address.is_broadcast() {
+            return self.shared.transmit(address, DataType::Request, messages).await;
+        }
+
+        if self.shared.options.batch_window.is_zero() {
+            return self.shared.request(address, messages).await;
+        }
+
+        batch::request(&self.shared, address, messages).await
+    }
+
+    /// Sends a request to every device on the bus. Nobody replies to
+    /// broadcasts, so it's sent once and succeeds as soon as it's written
+    pub async fn send_broadcast(&self, messages: &[Message]) -> Result<(), Error> {
+        self.shared.transmit(addresses::BROADCAST, DataType::Request, messages).await
+    }
+
+    /// Like [`Client::request`], but sent as a write. Some messages are
+    /// only accepted as one or the other
+    pub async fn write(&self, address: Address, messages: &[Message]) -> Result<(), Error> {
+        let reply = self.shared.send(address, DataType::Write, messages).await?;
+        expect_reply(reply, DataType::Ack)?;
+        Ok(())
+    }
+
+    /// Reads the current value of a message, computes a new value from it
+    /// with `f`, and if it differs, requests the new value and reads it
+    /// back to verify it took effect
+    pub async fn update<M: IsMessage>(&self, address: Address, f: impl FnOnce(&M::Value) -> M::Value)
+        -> Result<UpdateResult<M::Value>, Error>
+        where M::Value: PartialEq + Clone
+    {
+        let previous = self.read(address, &[M::ID]).await?.try_get::<M>()?;
+        let written = f(&previous);
+
+        if written == previous {
+            return Ok(UpdateResult::Unchanged(previous));
+        }
+
+        self.request(address, &[samsunghvac_protocol::message::new::<M>(written.clone())]).await?;
+
+        let actual = self.read(address, &[M::ID]).await?.get::<M>();
+
+        if actual.as_ref() == Some(&written) {
+            Ok(UpdateResult::Updated { previous, current: written })
+        } else {
+            Ok(UpdateResult::VerifyFailed { previous, written, actual })
+        }
+    }
+
+    /// Requests still awaiting a reply, including those queued behind
+    /// earlier requests to the same address, oldest first
+    pub fn inflight(&self) -> Vec<InflightRequest> {
+        let now = Instant::now();
+
+        let mut requests = self.shared.waiting.borrow().iter()
+            .map(|((address, packet_number), waiting)| InflightRequest {
+                request_id: waiting.request_id,
+                address: *address,
+                packet_number: *packet_number,
+                data_type: waiting.data_type,
+                elapsed: now.saturating_duration_since(waiting.started_at),
+                retries: waiting.retries.get(),
+            })
+            .collect::<Vec<_>>();
+
+        requests.sort_by_key(|request| request.request_id);
+        requests
+    }
+
+    /// Sends a packet and waits for its reply, giving up with
+    /// [`Error::DeadlineExceeded`] if none has arrived by `deadline`, even
+    /// if retries remain
+    pub async fn send_with_deadline(
+        &self,
+        destination: Address,
+        data_type: DataType,
+        messages: &[Message],
+        deadline: Instant,
+    ) -> Result<Box<Packet>, Error> {
+        tokio::time::timeout_at(deadline, self.shared.send(destination, data_type, messages)).await
+            .map_err(|_| Error::DeadlineExceeded)?
+    }
+
+    /// Sends a packet and waits for its reply, resending it as `policy`
+    /// decides rather than as the client is configured to
+    pub async fn send_with_retry_policy(
+        &self,
+        destination: Address,
+        data_type: DataType,
+        messages: &[Message],
+        policy: impl RetryPolicy + 'static,
+    ) -> Result<Box<Packet>, Error> {
+        self.shared.send_with_policy(destination, data_type, messages, Arc::new(policy)).await
+    }
+}
+
+impl Shared {
+    fn queue(&self, destination: Address) -> Rc<AsyncMutex<Option<Instant>>> {
+        self.queues.borrow_mut().entry(destination).or_default().clone()
+    }
+
+    async fn request(self: &Rc<Self>, address: Address, messages: &[Message]) -> Result<(), Error> {
         let reply = self.send(address, DataType::Request, messages).await?;
         expect_reply(reply, DataType::Ack)?;
         Ok(())
     }
 
-    async fn send(&self, destination: Address, data_type: DataType, messages: &[Message])
+    /// Sends a packet once without waiting for a reply, for destinations
+    /// that never reply
+    async fn transmit(&self, destination: Address, data_type: DataType, messages: &[Message])
+        -> Result<(), Error>
+    {
+        let messages = messages_vec(messages)?;
+
+        let packet = Packet {
+            source: self.options.address,
+            destination,
+            packet_info: PacketInfo::default(),
+            packet_type: PacketType::Normal,
+            packet_number: self.packet_number.fetch_add(1, Ordering::SeqCst),
+            data_type,
+            data: Data::Messages(messages),
+        };
+
+        let request_id = self.request_id.fetch_add(1, Ordering::Relaxed);
+        log::debug!("request {request_id}: {data_type} #{num} to {destination}, no reply expected: {messages}",
+            num = packet.packet_number,
+            messages = MessageSet::new(packet.data.messages()));
+
+        self.writer.lock().await.send(&packet).await?;
+        self.metrics.update(|m| m.packets_sent += 1);
+        Ok(())
+    }
+
+    /// Allocates a packet number not already awaiting a reply from
+    /// `destination`, so that replies can't be delivered to the wrong
+    /// request
+    fn next_packet_number(&self, destination: Address) -> Option<u8> {
+        let waiting = self.waiting.borrow();
+
+        (0..=u8::MAX)
+            .map(|_| self.packet_number.fetch_add(1, Ordering::SeqCst))
+            .find(|number| !waiting.contains_key(&(destination, *number)))
+    }
+
+    async fn send(self: &Rc<Self>, destination: Address, data_type: DataType, messages: &[Message])
         -> Result<Box<Packet>, Error>
     {
-        let messages = heapless::Vec::from_slice(messages).unwrap();
+        let retry = self.options.effective_retry_policy();
+        self.send_with_policy(destination, data_type, messages, retry).await
+    }
 
+    async fn send_with_policy(
+        self: &Rc<Self>,
+        destination: Address,
+        data_type: DataType,
+        messages: &[Message],
+        retry: Arc<dyn RetryPolicy>,
+    ) -> Result<Box<Packet>, Error> {
+        let messages = messages_vec(messages)?;
+        self.send_data_with_policy(destination, data_type, Data::Messages(messages), retry).await
+    }
+
+    async fn send_data(self: &Rc<Self>, destination: Address, data_type: DataType, data: Data)
+        -> Result<Box<Packet>, Error>
+    {
+        let retry = self.options.effective_retry_policy();
+        self.send_data_with_policy(destination, data_type, data, retry).await
+    }
+
+    async fn send_data_with_policy(
+        self: &Rc<Self>,
+        destination: Address,
+        data_type: DataType,
+        data: Data,
+        retry: Arc<dyn RetryPolicy>,
+    ) -> Result<Box<Packet>, Error> {
         // acquire packet number
-        let packet_number = self.next_packet_number();
+        let packet_number = self.next_packet_number(destination).ok_or(Error::TooManyPending)?;
 
         // build packet
         let packet = Box::new(Packet {
-            source: self.shared.address,
+            source: self.options.address,
             destination,
             packet_info: PacketInfo::default(),
             packet_type: PacketType::Normal,
             packet_number,
             data_type,
-            data: Data::Messages(messages),
+            data,
         });
 
+        let request_id = self.request_id.fetch_add(1, Ordering::Relaxed);
+
+        match &packet.data {
+            Data::Messages(messages) => {
+                log::debug!("request {request_id}: {data_type} #{packet_number} to {destination}: {messages}",
+                    messages = MessageSet::new(messages));
+            }
+            Data::Structure(structure) => {
+                log::debug!("request {request_id}: {data_type} #{packet_number} to {destination}: structure {}",
+                    structure.number);
+            }
+        }
+
+        // register for the reply before anything else can take the packet
+        // number. the entry is removed again however the send ends
+        let reply = PendingReply::new(self.clone(), destination, packet_number, data_type, request_id);
+
+        // dropped along with this future, which cancels the send at its
+        // next retry or wait for reply
+        let (_cancel, cancelled) = oneshot::channel();
+
         // send in a new task for cancel safety
-        let send_fut = send_with_retry(self.shared.clone(), packet);
+        let send_fut = send_with_retry(self.clone(), packet, reply, retry, cancelled, request_id);
         let reply = tokio::task::spawn_local(send_fut).await.unwrap()?;
 
         Ok(reply)
     }
 }
 
+/// Copies messages into a packet's message list, sorted and deduplicated
+fn messages_vec(messages: &[Message]) -> Result<MessagesVec, Error> {
+    let mut vec = MessagesVec::from_slice(messages)
+        .map_err(|_| Error::TooManyMessages(messages.len()))?;
+
+    packet::normalize_messages(&mut vec);
+    Ok(vec)
+}
+
+/// A request awaiting its reply, as listed by [`Client::inflight`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InflightRequest {
+    /// identifies the request in debug logs
+    pub request_id: u64,
+    pub address: Address,
+    pub packet_number: u8,
+    pub data_type: DataType,
+    /// time since the request was made
+    pub elapsed: Duration,
+    /// times the request has been resent for lack of a reply
+    pub retries: u32,
+}
+
+/// Outcome of [`Client::update`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateResult<T> {
+    /// the new value was the same as the current value, so nothing was
+    /// written
+    Unchanged(T),
+    /// the new value was written and read back
+    Updated { previous: T, current: T },
+    /// the new value was acknowledged, but reading it back gave a
+    /// different value, or none at all
+    VerifyFailed { previous: T, written: T, actual: Option<T> },
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error(transparent)]
@@ -138,14 +564,28 @@ pub enum Error {
     Send(#[from] SendPacketError),
     #[error("max retries exceeded")]
     MaxRetriesExceeded,
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
+    #[error("request cancelled")]
+    Cancelled,
+    #[error("too many requests awaiting replies")]
+    TooManyPending,
+    #[error("too many messages for one packet: {0}, at most {MAX_MESSAGE_COUNT}")]
+    TooManyMessages(usize),
+    /// a request merged with others into one packet failed. every request
+    /// in the batch shares the error
+    #[error(transparent)]
+    Batched(Arc<Error>),
     #[error("lost transport")]
     LostTransport,
-    #[error("received negative acknowledgement")]
-    Nack(Box<Packet>),
+    #[error("received negative acknowledgement {0}")]
+    Nack(Nack),
     #[error("unexpected reply {actual:?}, expected {expected:?}")]
     UnexpectedReply { actual: DataType, expected: DataType },
     #[error("missing message: {0}")]
     MissingMessage(MessageId),
+    #[error("unexpected structure reply {}, use read_structure", .0.number)]
+    StructureReply(Box<Structure>),
 }
 
 impl Drop for Client {
@@ -154,16 +594,44 @@ impl Drop for Client {
     }
 }
 
-async fn reader_task(shared: Rc<Shared>, mut rx: TransportReceiver) {
+async fn reader_task(shared: Rc<Shared>, mut rx: TransportReceiver, reconnect: Option<TransportOpt>) {
     loop {
-        let packet = match rx.read().await {
-            Ok(packet) => packet,
+        let read = rx.read_reporting(|err| shared.callbacks.on_parse_error(err)).await;
+
+        let (packet, meta) = match read {
+            Ok(result) => result,
             Err(err) => {
-                log::error!("reader task failed: {err}");
-                return;
+                shared.callbacks.on_disconnected(&err);
+
+                let Some(opt) = &reconnect else {
+                    log::error!("reader task failed: {err}");
+                    return;
+                };
+
+                log::warn!("lost transport: {err}");
+
+                // replies to packets in flight won't arrive on a new
+                // transport, dropping their senders fails them with
+                // LostTransport
+                shared.waiting.borrow_mut().clear();
+
+                rx = reopen_transport(&shared, opt).await;
+                shared.callbacks.on_connected();
+                continue;
             }
         };
 
+        shared.callbacks.on_raw_packet(&packet, &meta);
+        shared.metrics.update(|m| m.packets_received += 1);
+
+        if shared.tap.receiver_count() > 0 {
+            let _: Result<_, _> = shared.tap.send(packet.clone());
+        }
+
+        if packet.source != shared.options.address && shared.seen.borrow_mut().insert(packet.source) {
+            shared.callbacks.on_new_device(packet.source, packet.source.device_class());
+        }
+
         if packet.packet_type != PacketType::Normal {
             continue;
         }
@@ -174,82 +642,726 @@ async fn reader_task(shared: Rc<Shared>, mut rx: TransportReceiver) {
 
         match packet.data_type {
             DataType::Notification => {
-                let data = MessageSet::new(&messages);
+                shared.watches.update(packet.source, messages);
+
+                shared.recent.push(packet.source, messages);
+
+                let data = MessageSet::new(messages);
                 shared.callbacks.on_notification(packet.source, &data);
             }
             | DataType::Ack
             | DataType::Nack
             | DataType::Response => {
-                on_reply(&shared, packet);
+                on_reply(&shared, packet, meta);
+            }
+            | DataType::Read
+            | DataType::Write
+            | DataType::Request => {
+                respond::on_request(&shared, &packet, messages);
             }
-            _ => {}
+            DataType::Undefined => {}
         }
     }
 }
 
-fn on_reply(shared: &Shared, packet: Box<Packet>) {
+/// Reopens the transport with exponential backoff, installing the new
+/// sender and returning the new receiver
+async fn reopen_transport(shared: &Shared, opt: &TransportOpt) -> TransportReceiver {
+    let mut delay = RECONNECT_DELAY_MIN;
+
+    loop {
+        tokio::time::sleep(delay).await;
+
+        match transport::open(opt).await {
+            Ok((rx, tx)) => {
+                *shared.writer.lock().await = tx;
+                log::info!("reconnected to bus");
+                return rx;
+            }
+            Err(err) => {
+                log::warn!("reconnecting: {err}");
+                delay = cmp::min(delay * 2, RECONNECT_DELAY_MAX);
+            }
+        }
+    }
+}
+
+fn on_reply(shared: &Shared, packet: Box<Packet>, meta: PacketMeta) {
     // ignore reply-type packets if not addressed directly to us
-    if packet.destination != shared.address {
+    if packet.destination != shared.options.address {
         return;
     }
 
-    // look up waiting task (if any) by packet number
-    let reply_tx = {
+    // look up waiting task (if any) by packet number. only a reply from
+    // the request's destination completes it, another device may be using
+    // the same packet number
+    let waiting = {
         let mut waiting = shared.waiting.borrow_mut();
-        waiting.remove(&packet.packet_number)
+        waiting.remove(&(packet.source, packet.packet_number))
     };
 
     // send it to the waiting task
-    if let Some(reply_tx) = reply_tx {
-        let _: Result<_, _> = reply_tx.send(packet);
+    match waiting {
+        Some(waiting) => {
+            let _: Result<_, _> = waiting.reply_tx.send((packet, meta));
+        }
+        None => {
+            log::trace!("{} #{} from {} matches no request",
+                packet.data_type, packet.packet_number, packet.source);
+        }
     }
 }
 
 fn expect_reply(reply: Box<Packet>, data_type: DataType) -> Result<Box<Packet>, Error> {
     if reply.data_type == DataType::Nack {
-        return Err(Error::Nack(reply));
+        return Err(Error::Nack(Nack::new(reply)));
     }
 
     if reply.data_type != data_type {
         return Err(Error::UnexpectedReply { actual: reply.data_type, expected: data_type });
     }
 
-    return Ok(reply);
+    Ok(reply)
 }
 
-async fn send_with_retry(shared: Rc<Shared>, mut packet: Box<Packet>) -> Result<Box<Packet>, Error> {
-    let (reply_tx, mut reply_rx) = oneshot::channel();
+/// A reply being waited on. Removes its entry from the waiting map when
+/// dropped, so that abandoned requests don't hold their packet number
+struct PendingReply {
+    shared: Rc<Shared>,
+    destination: Address,
+    packet_number: u8,
+    request_id: u64,
+    reply_rx: oneshot::Receiver<Reply>,
+}
 
-    // lock waiting map and insert our reply oneshot
+impl PendingReply {
+    fn new(shared: Rc<Shared>, destination: Address, packet_number: u8, data_type: DataType, request_id: u64)
+        -> Self
     {
-        let mut waiting = shared.waiting.borrow_mut();
-        waiting.insert(packet.packet_number, reply_tx);
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        shared.waiting.borrow_mut().insert((destination, packet_number), Waiting {
+            request_id,
+            data_type,
+            started_at: Instant::now(),
+            retries: Cell::new(0),
+            reply_tx,
+        });
+
+        PendingReply { shared, destination, packet_number, request_id, reply_rx }
+    }
+
+    /// Records a resend, for [`Client::inflight`]
+    fn set_retries(&self, retries: u32) {
+        let waiting = self.shared.waiting.borrow();
+
+        if let Some(waiting) = waiting.get(&(self.destination, self.packet_number))
+            && waiting.request_id == self.request_id
+        {
+            waiting.retries.set(retries);
+        }
     }
+}
+
+impl Drop for PendingReply {
+    fn drop(&mut self) {
+        let mut waiting = self.shared.waiting.borrow_mut();
 
-    // TODO remove waiting oneshot on drop
+        // the entry is already gone if the reply arrived or the transport
+        // was lost, and its packet number may since belong to another
+        // request
+        let key = (self.destination, self.packet_number);
+
+        if waiting.get(&key).is_some_and(|w| w.request_id == self.request_id) {
+            waiting.remove(&key);
+        }
+    }
+}
+
+async fn send_with_retry(
+    shared: Rc<Shared>,
+    packet: Box<Packet>,
+    reply: PendingReply,
+    retry: Arc<dyn RetryPolicy>,
+    mut cancelled: oneshot::Receiver<()>,
+    request_id: u64,
+) -> Result<Box<Packet>, Error> {
+    // requests to one destination are sent one at a time, with at least
+    // the configured gap between them
+    let queue = shared.queue(packet.destination);
+    let mut last_done = unless_cancelled(&mut cancelled, request_id, queue.lock()).await?;
+
+    if let Some(last_done) = *last_done {
+        let ready = last_done + shared.options.request_gap;
+        unless_cancelled(&mut cancelled, request_id, tokio::time::sleep_until(ready)).await?;
+    }
+
+    let result = exchange(&shared, packet, reply, &*retry, &mut cancelled, request_id).await;
+    *last_done = Some(Instant::now());
+    result
+}
+
+async fn exchange(
+    shared: &Shared,
+    mut packet: Box<Packet>,
+    mut reply: PendingReply,
+    retry: &dyn RetryPolicy,
+    cancelled: &mut oneshot::Receiver<()>,
+    request_id: u64,
+) -> Result<Box<Packet>, Error> {
+    let mut retries = 0;
 
     loop {
+        // the caller may have gone away before this task first ran
+        if let Err(TryRecvError::Closed) = cancelled.try_recv() {
+            log::debug!("request {request_id}: cancelled");
+            return Err(Error::Cancelled);
+        }
+
         // lock writer to send packet:
-        {
+        let sent_at = {
             let mut writer = shared.writer.lock().await;
+            log::trace!("request {request_id}: sending, attempt {}", retries + 1);
             writer.send(&packet).await?;
-        }
+            transport::monotonic_now()
+        };
+
+        shared.metrics.update(|m| {
+            m.packets_sent += 1;
+            if retries == 0 {
+                m.requests += 1;
+            }
+        });
 
         // wait for reply:
-        match tokio::time::timeout(RETRY_DELAY, &mut reply_rx).await {
-            Ok(Ok(reply)) => { return Ok(reply); }
-            Ok(Err(_)) => { return Err(Error::LostTransport); }
+        let wait = tokio::time::timeout(shared.options.reply_timeout, &mut reply.reply_rx);
+
+        match unless_cancelled(cancelled, request_id, wait).await? {
+            Ok(Ok((reply, meta))) => {
+                let received_at = meta.received_at.unwrap_or_else(transport::monotonic_now);
+
+                shared.metrics.update(|m| {
+                    m.record_round_trip(received_at.saturating_sub(sent_at));
+                    if reply.data_type == DataType::Nack {
+                        m.nacks += 1;
+                    }
+                });
+
+                log::debug!("request {request_id}: {data_type} #{num} from {src} in {latency:?}",
+                    data_type = reply.data_type,
+                    num = reply.packet_number,
+                    src = reply.source,
+                    latency = received_at.saturating_sub(sent_at));
+                return Ok(reply);
+            }
+            Ok(Err(_)) => {
+                log::debug!("request {request_id}: lost transport");
+                return Err(Error::LostTransport);
+            }
             Err(_) => {
                 // timeout waiting on reply
-                // check if we've already exhausted max retries:
-                let retry_count = packet.packet_info.retry_count;
-                if retry_count == u2::MAX {
+                shared.metrics.update(|m| m.timeouts += 1);
+
+                // check if the policy has us give up:
+                let Some(delay) = retry.retry_delay(retries) else {
+                    log::debug!("request {request_id}: no reply, giving up after {retries} retries");
                     return Err(Error::MaxRetriesExceeded);
+                };
+
+                log::debug!("request {request_id}: no reply, retrying in {delay:?}");
+
+                // otherwise loop around and try sending it again. the retry
+                // count on the wire is only two bits, so it stops at max
+                retries += 1;
+                reply.set_retries(retries);
+                shared.metrics.update(|m| m.retries += 1);
+                let retry_count = packet.packet_info.retry_count;
+                if retry_count != u2::MAX {
+                    packet.packet_info.retry_count = retry_count + u2::new(1);
                 }
 
-                // otherwise loop around and try sending it again
-                packet.packet_info.retry_count = retry_count.wrapping_add(u2::new(1));
+                let delay = tokio::time::sleep(delay);
+                unless_cancelled(cancelled, request_id, delay).await?;
+            }
+        }
+    }
+}
+
+/// Runs `fut` to completion, unless the caller cancels the request first
+async fn unless_cancelled<T>(
+    cancelled: &mut oneshot::Receiver<()>,
+    request_id: u64,
+    fut: impl Future<Output = T>,
+) -> Result<T, Error> {
+    tokio::select! {
+        value = fut => Ok(value),
+        _ = cancelled => {
+            log::debug!("request {request_id}: cancelled");
+            Err(Error::Cancelled)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::path::PathBuf;
+
+    use samsunghvac_protocol::message::{self, types::{OperationMode, PowerSetting}};
+    use tokio::task::LocalSet;
+
+    use crate::mock::{self, MockDevice, Reply};
+    use crate::retry::ExponentialBackoff;
+
+    use super::*;
+
+    const UNIT: Address = addresses::INDOOR;
+
+    #[derive(Default)]
+    struct Events {
+        disconnected: Cell<u32>,
+        connected: Cell<u32>,
+    }
+
+    struct Recorder(Rc<Events>);
+
+    impl Callbacks for Recorder {
+        fn on_notification(&self, _: Address, _: &MessageSet) {}
+
+        fn on_disconnected(&self, _: &io::Error) {
+            self.0.disconnected.set(self.0.disconnected.get() + 1);
+        }
+
+        fn on_connected(&self) {
+            self.0.connected.set(self.0.connected.get() + 1);
+        }
+    }
+
+    fn pair(options: ClientOptions) -> (Client, MockDevice) {
+        mock::pair_with_options(options, Recorder(Default::default()))
+    }
+
+    async fn local<F: Future>(fut: F) -> F::Output {
+        LocalSet::new().run_until(fut).await
+    }
+
+    fn power(setting: PowerSetting) -> Message {
+        message::new::<message::Power>(setting)
+    }
+
+    fn mode(mode: OperationMode) -> Message {
+        message::new::<message::Mode>(mode)
+    }
+
+    fn reply_from(source: Address, data_type: DataType, packet_number: u8) -> Packet {
+        Packet {
+            source,
+            destination: addresses::CONTROLLER,
+            packet_info: PacketInfo::default(),
+            packet_type: PacketType::Normal,
+            data_type,
+            packet_number,
+            data: Data::Messages(Default::default()),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reply_from_another_device_is_ignored() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let (client, device) = pair(ClientOptions::new());
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::None);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::Ack);
+
+            let (result, ()) = tokio::join!(
+                client.request(UNIT, &on),
+                async {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+
+                    // same packet number, but from the outdoor unit
+                    let number = client.inflight()[0].packet_number;
+                    device.send(&reply_from(addresses::OUTDOOR, DataType::Ack, number)).await;
+
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    assert_eq!(client.inflight().len(), 1);
+                },
+            );
+
+            result.unwrap();
+            assert_eq!(client.metrics().retries, 1);
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_until_acked() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let (client, device) = pair(ClientOptions::new().max_retries(3));
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::None);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::None);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::Ack);
+
+            let start = Instant::now();
+            client.request(UNIT, &on).await.unwrap();
+
+            // two reply timeouts of a second each
+            assert_eq!(start.elapsed().as_secs(), 2);
+
+            let metrics = client.metrics();
+            assert_eq!(metrics.requests, 1);
+            assert_eq!(metrics.retries, 2);
+            assert_eq!(metrics.timeouts, 2);
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_retries() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let (client, device) = pair(ClientOptions::new().max_retries(1));
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::None);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::None);
+
+            let result = client.request(UNIT, &on).await;
+
+            assert!(matches!(result, Err(Error::MaxRetriesExceeded)));
+            assert!(client.inflight().is_empty());
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_policy_sets_delays() {
+        local(async {
+            let (client, device) = pair(ClientOptions::new());
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::None);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::None);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::Ack);
+
+            let policy = ExponentialBackoff::new(Duration::from_millis(100), 5);
+
+            let start = Instant::now();
+            client.send_with_retry_policy(UNIT, DataType::Request, &[power(PowerSetting::On)], policy)
+                .await.unwrap();
+
+            // two reply timeouts, then 100ms and 200ms of backoff
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(2300) && elapsed < Duration::from_millis(2350), "{elapsed:?}");
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_request_is_not_resent() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let (client, device) = pair(ClientOptions::new());
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::None);
+
+            let request = client.request(UNIT, &on);
+            assert!(tokio::time::timeout(Duration::from_millis(500), request).await.is_err());
+
+            // a resend would find no expectation in the script
+            tokio::time::sleep(Duration::from_secs(5)).await;
+
+            assert!(client.inflight().is_empty());
+            assert_eq!(client.metrics().packets_sent, 1);
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn nack_fails_request() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let (client, device) = pair(ClientOptions::new());
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::Nack);
+
+            let result = client.request(UNIT, &on).await;
+
+            assert!(matches!(result, Err(Error::Nack(_))));
+            assert_eq!(client.metrics().nacks, 1);
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn too_many_messages_is_an_error() {
+        local(async {
+            let (client, device) = pair(ClientOptions::new());
+
+            let messages = (0..=MAX_MESSAGE_COUNT as u16)
+                .map(|i| Message { id: MessageId(0x4000 + i), value: Value::Enum(0) })
+                .collect::<Vec<_>>();
+
+            let result = client.request(UNIT, &messages).await;
+            assert!(matches!(result, Err(Error::TooManyMessages(256))));
+
+            let result = client.send_broadcast(&messages).await;
+            assert!(matches!(result, Err(Error::TooManyMessages(256))));
+
+            assert_eq!(client.metrics().packets_sent, 0);
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_within_window_are_batched() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let cool = [mode(OperationMode::Cool)];
+            let options = ClientOptions::new().batch_window(Duration::from_millis(50));
+            let (client, device) = pair(options);
+            device.expect_request(UNIT, &[power(PowerSetting::On), mode(OperationMode::Cool)], Reply::Ack);
+
+            let (a, b) = tokio::join!(
+                client.request(UNIT, &on),
+                client.request(UNIT, &cool),
+            );
+
+            a.unwrap();
+            b.unwrap();
+            assert_eq!(client.metrics().requests, 1);
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batched_requests_share_nack() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let cool = [mode(OperationMode::Cool)];
+            let options = ClientOptions::new().batch_window(Duration::from_millis(50));
+            let (client, device) = pair(options);
+            device.expect_request(UNIT, &[power(PowerSetting::On), mode(OperationMode::Cool)], Reply::Nack);
+
+            let (a, b) = tokio::join!(
+                client.request(UNIT, &on),
+                client.request(UNIT, &cool),
+            );
+
+            for result in [a, b] {
+                let Err(Error::Batched(err)) = result else { panic!("expected batched error") };
+                assert!(matches!(*err, Error::Nack(_)));
             }
+
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_to_one_address_are_paced() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let cool = [mode(OperationMode::Cool)];
+            let options = ClientOptions::new().request_gap(Duration::from_millis(200));
+            let (client, device) = pair(options);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::Ack);
+            device.expect_request(UNIT, &[mode(OperationMode::Cool)], Reply::Ack);
+
+            let start = Instant::now();
+
+            let (a, b) = tokio::join!(
+                client.request(UNIT, &on),
+                client.request(UNIT, &cool),
+            );
+
+            a.unwrap();
+            b.unwrap();
+
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_secs(1), "{elapsed:?}");
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_reads_are_split() {
+        local(async {
+            let (client, device) = pair(ClientOptions::new());
+
+            let attrs = (0..15).map(|i| MessageId(0x4000 + i)).collect::<Vec<_>>();
+            let values = attrs.iter()
+                .map(|id| Message { id: *id, value: Value::Enum(id.0 as u8) })
+                .collect::<Vec<_>>();
+
+            device.expect_read(UNIT, &attrs[..MAX_READ_ATTRS], &values[..MAX_READ_ATTRS]);
+            device.expect_read(UNIT, &attrs[MAX_READ_ATTRS..], &values[MAX_READ_ATTRS..]);
+
+            let data = client.read(UNIT, &attrs).await.unwrap();
+
+            assert_eq!(data.messages(), values.as_slice());
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn update_writes_and_verifies() {
+        local(async {
+            let (client, device) = pair(ClientOptions::new());
+            device.expect_read(UNIT, &[message::Power::ID], &[power(PowerSetting::Off)]);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::Ack);
+            device.expect_read(UNIT, &[message::Power::ID], &[power(PowerSetting::On)]);
+
+            let result = client.update::<message::Power>(UNIT, |_| PowerSetting::On).await.unwrap();
+
+            assert_eq!(result, UpdateResult::Updated { previous: PowerSetting::Off, current: PowerSetting::On });
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn update_skips_unchanged_value() {
+        local(async {
+            let (client, device) = pair(ClientOptions::new());
+            device.expect_read(UNIT, &[message::Power::ID], &[power(PowerSetting::On)]);
+
+            let result = client.update::<message::Power>(UNIT, |_| PowerSetting::On).await.unwrap();
+
+            assert_eq!(result, UpdateResult::Unchanged(PowerSetting::On));
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn update_reports_failed_verify() {
+        local(async {
+            let (client, device) = pair(ClientOptions::new());
+            device.expect_read(UNIT, &[message::Power::ID], &[power(PowerSetting::Off)]);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::Ack);
+            device.expect_read(UNIT, &[message::Power::ID], &[power(PowerSetting::Off)]);
+
+            let result = client.update::<message::Power>(UNIT, |_| PowerSetting::On).await.unwrap();
+
+            assert_eq!(result, UpdateResult::VerifyFailed {
+                previous: PowerSetting::Off,
+                written: PowerSetting::On,
+                actual: Some(PowerSetting::Off),
+            });
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn inflight_tracks_retries() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let (client, device) = pair(ClientOptions::new());
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::None);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::None);
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::Ack);
+
+            let (result, ()) = tokio::join!(
+                client.request(UNIT, &on),
+                async {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+
+                    let inflight = client.inflight();
+                    assert_eq!(inflight.len(), 1);
+                    assert_eq!(inflight[0].address, UNIT);
+                    assert_eq!(inflight[0].data_type, DataType::Request);
+                    assert_eq!(inflight[0].elapsed, Duration::from_millis(500));
+                    assert_eq!(inflight[0].retries, 0);
+
+                    // past the first reply timeout
+                    tokio::time::sleep(Duration::from_millis(600)).await;
+                    assert_eq!(client.inflight()[0].retries, 1);
+                },
+            );
+
+            result.unwrap();
+            assert!(client.inflight().is_empty());
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn inflight_includes_queued_requests() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let cool = [mode(OperationMode::Cool)];
+            let (client, device) = pair(ClientOptions::new());
+            device.expect_request(UNIT, &[power(PowerSetting::On)], Reply::Ack);
+            device.expect_request(UNIT, &[mode(OperationMode::Cool)], Reply::Ack);
+
+            let (a, b, ()) = tokio::join!(
+                client.request(UNIT, &on),
+                client.request(UNIT, &cool),
+                async {
+                    let inflight = client.inflight();
+                    assert_eq!(inflight.len(), 2);
+                    assert!(inflight[0].request_id < inflight[1].request_id);
+                },
+            );
+
+            a.unwrap();
+            b.unwrap();
+            device.verify();
+        }).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lost_transport_is_not_reopened_for_from_transport() {
+        local(async {
+            let on = [power(PowerSetting::On)];
+            let events = Rc::new(Events::default());
+            let (client, device) = mock::pair_with_options(ClientOptions::new().reconnect(true), Recorder(events.clone()));
+
+            drop(device);
+            tokio::time::sleep(Duration::from_secs(5)).await;
+
+            assert_eq!(events.disconnected.get(), 1);
+            assert_eq!(events.connected.get(), 0);
+            assert!(client.request(UNIT, &on).await.is_err());
+        }).await;
+    }
+
+    /// A bus whose command exits straight away, so the transport is lost
+    /// as soon as it's opened
+    fn vanishing_bus() -> TransportOpt {
+        TransportOpt { bus: PathBuf::from("exec:exit 0") }
+    }
+
+    /// Waits in real time, as the bus is a real process, for `done`
+    async fn wait_for(done: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
         }
     }
+
+    #[tokio::test]
+    async fn reconnects_when_asked() {
+        local(async {
+            let events = Rc::new(Events::default());
+            let options = ClientOptions::new().reconnect(true);
+            let _client = Client::connect(&vanishing_bus(), options, Recorder(events.clone())).await.unwrap();
+
+            wait_for(|| events.connected.get() > 0).await;
+
+            assert!(events.disconnected.get() >= 1);
+            assert!(events.connected.get() >= 1);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn stays_disconnected_by_default() {
+        local(async {
+            let events = Rc::new(Events::default());
+            let _client = Client::connect(&vanishing_bus(), ClientOptions::new(), Recorder(events.clone()))
+                .await.unwrap();
+
+            wait_for(|| events.disconnected.get() > 0).await;
+
+            // longer than the first reconnect delay
+            tokio::time::sleep(RECONNECT_DELAY_MIN * 2).await;
+
+            assert_eq!(events.disconnected.get(), 1);
+            assert_eq!(events.connected.get(), 0);
+        }).await;
+    }
 }
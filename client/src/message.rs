@@ -20,7 +20,7 @@ impl<'a> MessageSet<'a> {
 
     pub fn get<M: IsMessage>(&self) -> Option<M::Value> {
         for message in self.messages.as_ref() {
-            if let Some(value) = M::get(&message) {
+            if let Some(value) = M::get(message) {
                 return Some(value);
             }
         }
@@ -51,3 +51,60 @@ impl<'a> Display for MessageSet<'a> {
         Ok(())
     }
 }
+
+/// Reads several messages from one address in a single read, returning a
+/// struct with a field for each. Fields declared as `Option<M>` are `None`
+/// if the reply leaves them out, any other missing field fails the read
+/// with [`Error::MissingMessage`]:
+///
+/// ```ignore
+/// let state = read_messages!(client, address, {
+///     power: message::Power,
+///     mode: message::Mode,
+///     temp: Option<message::SetTemp>,
+/// }).await?;
+/// ```
+#[macro_export]
+macro_rules! read_messages {
+    ($client:expr, $address:expr, { $($fields:tt)* }) => {
+        $crate::read_messages!(@munch ($client, $address) [] $($fields)*)
+    };
+
+    // sort fields into optional and required:
+    (@munch $args:tt [$($out:tt)*] $field:ident : Option<$msg:path> $(, $($rest:tt)*)?) => {
+        $crate::read_messages!(@munch $args [$($out)* ($field optional $msg)] $($($rest)*)?)
+    };
+    (@munch $args:tt [$($out:tt)*] $field:ident : $msg:path $(, $($rest:tt)*)?) => {
+        $crate::read_messages!(@munch $args [$($out)* ($field required $msg)] $($($rest)*)?)
+    };
+
+    (@munch ($client:expr, $address:expr) [$(($field:ident $kind:ident $msg:path))*]) => {
+        async {
+            struct ReadMessages {
+                $( $field: $crate::read_messages!(@type $kind $msg), )*
+            }
+
+            let set = $client.read($address, &[
+                $( <$msg as $crate::message::__private::IsMessage>::ID, )*
+            ]).await?;
+
+            Ok::<_, $crate::Error>(ReadMessages {
+                $( $field: $crate::read_messages!(@value $kind $msg, set), )*
+            })
+        }
+    };
+
+    (@type required $msg:path) => {
+        <$msg as $crate::message::__private::IsMessage>::Value
+    };
+    (@type optional $msg:path) => {
+        Option<<$msg as $crate::message::__private::IsMessage>::Value>
+    };
+    (@value required $msg:path, $set:ident) => { $set.try_get::<$msg>()? };
+    (@value optional $msg:path, $set:ident) => { $set.get::<$msg>() };
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use samsunghvac_protocol::message::IsMessage;
+}
@@ -0,0 +1,55 @@
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::Client;
+
+/// Counters of the client's traffic since it was created, for reporting
+/// bus health. Read with [`Client::metrics`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ClientMetrics {
+    /// packets written to the transport, counting resends and replies
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    /// requests sent expecting a reply
+    pub requests: u64,
+    /// resends of requests that got no reply in time
+    pub retries: u64,
+    /// waits for a reply that timed out, whether or not the request was
+    /// then resent
+    pub timeouts: u64,
+    pub nacks: u64,
+    /// replies received to requests, the count behind the round trip times
+    pub replies: u64,
+    /// sum of the time from sending each request to receiving its reply
+    pub round_trip_total: Duration,
+    pub round_trip_max: Duration,
+}
+
+impl ClientMetrics {
+    pub fn round_trip_mean(&self) -> Option<Duration> {
+        let replies = u32::try_from(self.replies).ok().filter(|n| *n > 0)?;
+        Some(self.round_trip_total / replies)
+    }
+
+    pub(crate) fn record_round_trip(&mut self, latency: Duration) {
+        self.replies += 1;
+        self.round_trip_total += latency;
+        self.round_trip_max = self.round_trip_max.max(latency);
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct MetricsCell(RefCell<ClientMetrics>);
+
+impl MetricsCell {
+    pub fn update(&self, f: impl FnOnce(&mut ClientMetrics)) {
+        f(&mut self.0.borrow_mut());
+    }
+}
+
+impl Client {
+    /// Snapshot of the client's traffic counters
+    pub fn metrics(&self) -> ClientMetrics {
+        self.shared.metrics.0.borrow().clone()
+    }
+}
@@ -0,0 +1,239 @@
+//! Scriptable fake device for unit testing code built on [`Client`].
+//!
+//! [`pair`] returns a real [`Client`] connected over an in-memory transport
+//! to a [`MockDevice`]. The device answers the client's packets from a
+//! script of expectations, in order, and can inject notifications. Call
+//! [`MockDevice::verify`] at the end of a test to check that every
+//! expectation was met.
+//!
+//! Like [`Client`] itself, this must be used from within a
+//! [`tokio::task::LocalSet`].
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use samsunghvac_protocol::packet::{
+    self, addresses, Address, Data, DataType, Message, MessageId, MessagesVec, Packet, PacketInfo,
+    PacketType,
+};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::task;
+
+use crate::transport::{self, TransportReceiver, TransportSender};
+use crate::{Callbacks, Client, ClientOptions};
+
+/// Creates a client connected to a new mock device
+pub fn pair(callbacks: impl Callbacks + 'static) -> (Client, MockDevice) {
+    pair_with_options(ClientOptions::default(), callbacks)
+}
+
+/// Like [`pair`], with options for the client
+pub fn pair_with_options(options: ClientOptions, callbacks: impl Callbacks + 'static) -> (Client, MockDevice) {
+    let (client_transport, (rx, tx)) = transport::pair();
+
+    let client = Client::from_transport(client_transport, options, Box::new(callbacks));
+
+    let shared = Rc::new(MockShared {
+        writer: AsyncMutex::new(tx),
+        script: Default::default(),
+        failures: Default::default(),
+    });
+
+    let task = task::spawn_local(device_task(shared.clone(), rx));
+
+    (client, MockDevice { shared, task })
+}
+
+/// How the device replies to a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    Ack,
+    Nack,
+    /// Never reply, so the client times out and retries. Each retry is
+    /// matched against the next expectation in the script
+    None,
+}
+
+#[derive(Debug)]
+enum Expectation {
+    Read { address: Address, attrs: Vec<MessageId>, reply: Vec<Message> },
+    /// a request or write, as given by `data_type`
+    Request { address: Address, data_type: DataType, messages: Vec<Message>, reply: Reply },
+}
+
+pub struct MockDevice {
+    shared: Rc<MockShared>,
+    task: task::JoinHandle<()>,
+}
+
+struct MockShared {
+    writer: AsyncMutex<TransportSender>,
+    script: RefCell<VecDeque<Expectation>>,
+    failures: RefCell<Vec<String>>,
+}
+
+impl MockDevice {
+    /// Expects a read of exactly `attrs` from `address`, in any order,
+    /// and responds with `reply`
+    pub fn expect_read(&self, address: Address, attrs: &[MessageId], reply: &[Message]) {
+        self.shared.script.borrow_mut().push_back(Expectation::Read {
+            address,
+            attrs: attrs.to_vec(),
+            reply: reply.to_vec(),
+        });
+    }
+
+    /// Expects a request carrying exactly `messages` to `address`, in any
+    /// order, and replies to it with `reply`
+    pub fn expect_request(&self, address: Address, messages: &[Message], reply: Reply) {
+        self.expect_command(address, DataType::Request, messages, reply);
+    }
+
+    /// Expects a write carrying exactly `messages` to `address`, in any
+    /// order, and replies to it with `reply`
+    pub fn expect_write(&self, address: Address, messages: &[Message], reply: Reply) {
+        self.expect_command(address, DataType::Write, messages, reply);
+    }
+
+    fn expect_command(&self, address: Address, data_type: DataType, messages: &[Message], reply: Reply) {
+        let mut messages = MessagesVec::from_slice(messages)
+            .expect("too many messages for one packet");
+        packet::normalize_messages(&mut messages);
+
+        self.shared.script.borrow_mut().push_back(Expectation::Request {
+            address,
+            data_type,
+            messages: messages.to_vec(),
+            reply,
+        });
+    }
+
+    /// Sends a notification from `source` to the client
+    pub async fn notify(&self, source: Address, messages: &[Message]) {
+        let messages = MessagesVec::from_slice(messages)
+            .expect("too many messages for one packet");
+
+        let packet = Packet {
+            source,
+            destination: addresses::BROADCAST,
+            packet_info: PacketInfo::default(),
+            packet_type: PacketType::Normal,
+            data_type: DataType::Notification,
+            packet_number: 0,
+            data: Data::Messages(messages),
+        };
+
+        send(&self.shared, &packet).await;
+    }
+
+    /// Sends any packet to the client, for replies and traffic from other
+    /// devices that the script can't express
+    pub async fn send(&self, packet: &Packet) {
+        send(&self.shared, packet).await;
+    }
+
+    /// Number of expectations not yet met
+    pub fn pending(&self) -> usize {
+        self.shared.script.borrow().len()
+    }
+
+    /// Panics if any packet did not match its expectation, or if any
+    /// expectation was not met
+    pub fn verify(&self) {
+        let failures = self.shared.failures.borrow();
+        let script = self.shared.script.borrow();
+
+        if !failures.is_empty() {
+            panic!("mock device: unexpected packets:\n{}", failures.join("\n"));
+        }
+
+        if !script.is_empty() {
+            panic!("mock device: unmet expectations: {:?}", script);
+        }
+    }
+}
+
+impl Drop for MockDevice {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn device_task(shared: Rc<MockShared>, mut rx: TransportReceiver) {
+    while let Ok(packet) = rx.read().await {
+        let Some(reply) = on_packet(&shared, &packet) else {
+            continue;
+        };
+
+        send(&shared, &reply).await;
+    }
+}
+
+fn on_packet(shared: &MockShared, packet: &Packet) -> Option<Packet> {
+    let expectation = shared.script.borrow_mut().pop_front();
+
+    let Some(expectation) = expectation else {
+        fail(shared, format!("no expectation for packet: {packet:?}"));
+        return None;
+    };
+
+    let Data::Messages(messages) = &packet.data else {
+        fail(shared, format!("expected messages, got structure: {packet:?}"));
+        return None;
+    };
+
+    match expectation {
+        Expectation::Read { address, attrs, reply } => {
+            let mut ids = messages.iter().map(|msg| msg.id).collect::<Vec<_>>();
+            let mut expected = attrs.clone();
+            ids.sort();
+            expected.sort();
+
+            if packet.data_type != DataType::Read || packet.destination != address || ids != expected {
+                fail(shared, format!("expected read of {attrs:?} from {address}, got: {packet:?}"));
+                return None;
+            }
+
+            Some(reply_to(packet, DataType::Response, &reply))
+        }
+        Expectation::Request { address, data_type, messages: expected, reply } => {
+            if packet.data_type != data_type || packet.destination != address || messages[..] != expected[..] {
+                fail(shared, format!("expected {data_type} of {expected:?} to {address}, got: {packet:?}"));
+                return None;
+            }
+
+            match reply {
+                Reply::Ack => Some(reply_to(packet, DataType::Ack, &[])),
+                Reply::Nack => Some(reply_to(packet, DataType::Nack, &[])),
+                Reply::None => None,
+            }
+        }
+    }
+}
+
+fn reply_to(packet: &Packet, data_type: DataType, messages: &[Message]) -> Packet {
+    let messages = MessagesVec::from_slice(messages)
+        .expect("too many messages for one packet");
+
+    Packet {
+        source: packet.destination,
+        destination: packet.source,
+        packet_info: PacketInfo::default(),
+        packet_type: PacketType::Normal,
+        data_type,
+        packet_number: packet.packet_number,
+        data: Data::Messages(messages),
+    }
+}
+
+fn fail(shared: &MockShared, failure: String) {
+    log::error!("mock device: {failure}");
+    shared.failures.borrow_mut().push(failure);
+}
+
+async fn send(shared: &MockShared, packet: &Packet) {
+    let mut writer = shared.writer.lock().await;
+    writer.send(packet).await.expect("mock device: send failed");
+}
+
@@ -0,0 +1,84 @@
+use std::fmt::{self, Display};
+
+use samsunghvac_protocol::packet::{Data, Message, Packet};
+
+/// A negative acknowledgement from a unit. There's no documented format
+/// for why a request was refused, so this only exposes what the nack
+/// carried, usually nothing
+#[derive(Debug, Clone)]
+pub struct Nack {
+    packet: Box<Packet>,
+}
+
+impl Nack {
+    pub(crate) fn new(packet: Box<Packet>) -> Self {
+        Nack { packet }
+    }
+
+    /// Messages the nack carried, empty if it carried none or a structure
+    pub fn messages(&self) -> &[Message] {
+        match &self.packet.data {
+            Data::Messages(messages) => messages,
+            Data::Structure(_) => &[],
+        }
+    }
+
+    pub fn packet(&self) -> &Packet {
+        &self.packet
+    }
+
+    pub fn into_packet(self) -> Box<Packet> {
+        self.packet
+    }
+}
+
+impl Display for Nack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "from {}", self.packet.source)?;
+        for message in self.messages() {
+            write!(f, " {}={}", message.id, message.value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use samsunghvac_protocol::packet::{addresses, DataType, MessageId, MessagesVec, PacketInfo, PacketType, Structure, StructureData, Value};
+
+    use super::*;
+
+    fn nack(data: Data) -> Nack {
+        Nack::new(Box::new(Packet {
+            source: addresses::INDOOR,
+            destination: addresses::BROADCAST,
+            packet_info: PacketInfo::default(),
+            packet_type: PacketType::Normal,
+            data_type: DataType::Nack,
+            packet_number: 0,
+            data,
+        }))
+    }
+
+    #[test]
+    fn exposes_carried_messages() {
+        let message = Message { id: MessageId(0x4000), value: Value::Enum(1) };
+        let nack = nack(Data::Messages([message.clone()].into_iter().collect()));
+
+        assert_eq!(nack.messages(), std::slice::from_ref(&message));
+        assert!(nack.to_string().ends_with(&format!(" {}={}", message.id, message.value)));
+    }
+
+    #[test]
+    fn empty_nack() {
+        let nack = nack(Data::Messages(MessagesVec::new()));
+        assert!(nack.messages().is_empty());
+        assert_eq!(nack.to_string(), format!("from {}", addresses::INDOOR));
+    }
+
+    #[test]
+    fn structure_nack_has_no_messages() {
+        let nack = nack(Data::Structure(Structure { number: MessageId(0), data: StructureData::new() }));
+        assert!(nack.messages().is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use samsunghvac_protocol::packet::{addresses, Address};
+
+use crate::retry::{FixedDelay, RetryPolicy};
+
+/// Options for [`Client`](crate::Client), built up from the defaults by
+/// chaining setters on [`ClientOptions::new`]
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub(crate) address: Address,
+    pub(crate) reply_timeout: Duration,
+    pub(crate) retry_delay: Duration,
+    pub(crate) max_retries: u8,
+    pub(crate) retry_policy: Option<Arc<dyn RetryPolicy>>,
+    pub(crate) reconnect: bool,
+    pub(crate) batch_window: Duration,
+    pub(crate) request_gap: Duration,
+    pub(crate) recent_notifications: usize,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            address: addresses::CONTROLLER,
+            reply_timeout: Duration::from_secs(1),
+            retry_delay: Duration::ZERO,
+            max_retries: 3,
+            retry_policy: None,
+            reconnect: false,
+            batch_window: Duration::ZERO,
+            request_gap: Duration::ZERO,
+            recent_notifications: 0,
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Alias for `ClientOptions::default`
+    pub fn new() -> Self {
+        ClientOptions::default()
+    }
+
+    /// Source address of packets sent by the client. Every controller on
+    /// a bus needs its own, as replies are routed by it
+    pub fn address(mut self, address: Address) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// How long to wait for a reply before retrying
+    pub fn reply_timeout(mut self, timeout: Duration) -> Self {
+        self.reply_timeout = timeout;
+        self
+    }
+
+    /// How long to pause after a timeout before retrying. Ignored if a
+    /// [`retry_policy`](Self::retry_policy) is set
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Number of times to resend a packet that got no reply, before
+    /// failing with [`Error::MaxRetriesExceeded`](crate::Error::MaxRetriesExceeded).
+    /// Ignored if a [`retry_policy`](Self::retry_policy) is set
+    pub fn max_retries(mut self, retries: u8) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Decides when to resend requests that got no reply, in place of the
+    /// fixed [`retry_delay`](Self::retry_delay) and
+    /// [`max_retries`](Self::max_retries)
+    pub fn retry_policy(mut self, policy: impl RetryPolicy + 'static) -> Self {
+        self.retry_policy = Some(Arc::new(policy));
+        self
+    }
+
+    pub(crate) fn effective_retry_policy(&self) -> Arc<dyn RetryPolicy> {
+        match &self.retry_policy {
+            Some(policy) => policy.clone(),
+            None => Arc::new(FixedDelay {
+                delay: self.retry_delay,
+                max_retries: self.max_retries.into(),
+            }),
+        }
+    }
+
+    /// Whether to reopen the transport with backoff when it's lost, rather
+    /// than failing every request from then on. Off by default. Only
+    /// applies to clients created with [`Client::connect`](crate::Client::connect)
+    pub fn reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// How long to hold a request for others to the same address to be
+    /// merged into it, so that a burst of small changes is sent as one
+    /// packet. Zero, the default, sends every request on its own
+    pub fn batch_window(mut self, window: Duration) -> Self {
+        self.batch_window = window;
+        self
+    }
+
+    /// Least time between one request to an address finishing and the
+    /// next being sent. Requests to the same address are always sent one
+    /// at a time, some units nack requests that arrive too close together
+    pub fn request_gap(mut self, gap: Duration) -> Self {
+        self.request_gap = gap;
+        self
+    }
+
+    /// How many of the latest notifications from each address to keep for
+    /// [`Client::recent`](crate::Client::recent). Zero, the default, keeps
+    /// none
+    pub fn recent_notifications(mut self, count: usize) -> Self {
+        self.recent_notifications = count;
+        self
+    }
+}
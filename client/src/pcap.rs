@@ -0,0 +1,335 @@
+//! Reading and writing captures in pcapng format, for use with Wireshark
+//! and other existing tooling.
+//!
+//! Each packet is stored as its complete wire frame, including preamble,
+//! under the [`LINKTYPE_USER0`] link type. Timestamps are in microseconds.
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use samsunghvac_protocol::frame::MAX_FRAME_SIZE;
+use samsunghvac_protocol::packet::{Packet, ParseWireError, SerializePacketError};
+use thiserror::Error;
+
+/// Link type for private use, which NASA captures are written with
+pub const LINKTYPE_USER0: u16 = 147;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x00000001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x00000006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+/// Upper bound on block size accepted when reading, well above any frame
+const MAX_BLOCK_SIZE: usize = 64 * 1024;
+
+#[derive(Error, Debug)]
+pub enum PcapError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("invalid pcapng: {0}")]
+    Format(&'static str),
+    #[error("unsupported link type: {0}")]
+    LinkType(u16),
+    #[error(transparent)]
+    Serialize(#[from] SerializePacketError),
+    #[error(transparent)]
+    Parse(#[from] ParseWireError),
+}
+
+/// Writes packets to a pcapng capture
+pub struct PcapWriter<W> {
+    out: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the section header and interface description, ready for
+    /// packets to follow
+    pub fn new(mut out: W) -> Result<Self, PcapError> {
+        // section header: byte order magic, version 1.0, unknown length
+        let mut body = Vec::new();
+        body.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend(1u16.to_le_bytes());
+        body.extend(0u16.to_le_bytes());
+        body.extend((-1i64).to_le_bytes());
+        write_block(&mut out, SECTION_HEADER_BLOCK, &body)?;
+
+        // interface description: link type, reserved, snap length
+        let mut body = Vec::new();
+        body.extend(LINKTYPE_USER0.to_le_bytes());
+        body.extend(0u16.to_le_bytes());
+        body.extend((MAX_FRAME_SIZE as u32).to_le_bytes());
+        write_block(&mut out, INTERFACE_DESCRIPTION_BLOCK, &body)?;
+
+        Ok(PcapWriter { out })
+    }
+
+    pub fn write(&mut self, timestamp: SystemTime, packet: &Packet) -> Result<(), PcapError> {
+        let frame = packet.serialize_to_vec()?;
+        let len = frame.len();
+
+        let micros = timestamp.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        // enhanced packet: interface, timestamp, captured and original
+        // length, then the frame padded to 32 bits
+        let mut body = Vec::new();
+        body.extend(0u32.to_le_bytes());
+        body.extend(((micros >> 32) as u32).to_le_bytes());
+        body.extend((micros as u32).to_le_bytes());
+        body.extend((len as u32).to_le_bytes());
+        body.extend((len as u32).to_le_bytes());
+        body.extend(&frame);
+        body.resize(body.len().next_multiple_of(4), 0);
+        write_block(&mut self.out, ENHANCED_PACKET_BLOCK, &body)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), PcapError> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+fn write_block(out: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_len = (body.len() + 12) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&total_len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&total_len.to_le_bytes())?;
+    Ok(())
+}
+
+/// A packet read back from a capture
+pub struct CapturedPacket {
+    pub timestamp: SystemTime,
+    pub packet: Box<Packet>,
+}
+
+/// Reads packets from a pcapng capture. Frames that fail to parse are
+/// yielded as errors without ending iteration, any other error ends it.
+/// Offsets in packet errors count from the start of the capture.
+pub struct PcapReader<R> {
+    input: R,
+    big_endian: bool,
+    link_types: Vec<u16>,
+    done: bool,
+    /// bytes read from the capture so far
+    pos: usize,
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(input: R) -> Self {
+        PcapReader { input, big_endian: false, link_types: Vec::new(), done: false, pos: 0 }
+    }
+
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, PcapError> {
+        loop {
+            let Some((block_type, body_offset, body)) = self.read_block()? else {
+                return Ok(None);
+            };
+
+            match block_type {
+                SECTION_HEADER_BLOCK => {
+                    // byte order was already determined in read_block
+                    self.link_types.clear();
+                }
+                INTERFACE_DESCRIPTION_BLOCK => {
+                    let link_type = self.u16_at(&body, 0)?;
+                    self.link_types.push(link_type);
+                }
+                ENHANCED_PACKET_BLOCK => {
+                    let interface = self.u32_at(&body, 0)? as usize;
+                    let link_type = *self.link_types.get(interface)
+                        .ok_or(PcapError::Format("packet for undescribed interface"))?;
+
+                    if link_type != LINKTYPE_USER0 {
+                        return Err(PcapError::LinkType(link_type));
+                    }
+
+                    let micros = u64::from(self.u32_at(&body, 4)?) << 32
+                        | u64::from(self.u32_at(&body, 8)?);
+                    let captured_len = self.u32_at(&body, 12)? as usize;
+
+                    let frame = body.get(20..20 + captured_len)
+                        .ok_or(PcapError::Format("packet data exceeds block"))?;
+
+                    let packet = Packet::parse_wire(frame)
+                        .map_err(|err| err.offset_by(body_offset + 20))?;
+
+                    return Ok(Some(CapturedPacket {
+                        timestamp: UNIX_EPOCH + Duration::from_micros(micros),
+                        packet: Box::new(packet),
+                    }));
+                }
+                // skip other block types
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads a block, returning its type, the offset of its body in the
+    /// capture and the body, or `None` at end of input
+    fn read_block(&mut self) -> Result<Option<(u32, usize, Vec<u8>)>, PcapError> {
+        let mut header = [0u8; 8];
+
+        match self.read_exact(&mut header[..4]) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+
+        self.read_exact(&mut header[4..])?;
+
+        let block_type = u32::from_le_bytes(header[..4].try_into().unwrap());
+
+        // the section header block type reads the same in either byte
+        // order, its byte order magic decides how the rest is read:
+        if block_type == SECTION_HEADER_BLOCK {
+            let mut magic = [0u8; 4];
+            self.read_exact(&mut magic)?;
+
+            self.big_endian = match u32::from_le_bytes(magic) {
+                BYTE_ORDER_MAGIC => false,
+                magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                _ => return Err(PcapError::Format("bad byte order magic")),
+            };
+
+            let total_len = self.u32_at(&header, 4)? as usize;
+            let body_offset = self.pos;
+            let body = self.read_body(total_len, 4)?;
+            return Ok(Some((block_type, body_offset, body)));
+        }
+
+        let block_type = self.u32_at(&header, 0)?;
+        let total_len = self.u32_at(&header, 4)? as usize;
+        let body_offset = self.pos;
+        let body = self.read_body(total_len, 0)?;
+        Ok(Some((block_type, body_offset, body)))
+    }
+
+    /// Reads the rest of a block after its header and `consumed` bytes of
+    /// body, returning the remaining body without the trailing length
+    fn read_body(&mut self, total_len: usize, consumed: usize) -> Result<Vec<u8>, PcapError> {
+        if total_len < 12 + consumed || !total_len.is_multiple_of(4) || total_len > MAX_BLOCK_SIZE {
+            return Err(PcapError::Format("bad block length"));
+        }
+
+        let mut body = vec![0; total_len - 8 - consumed];
+        self.read_exact(&mut body)?;
+        body.truncate(body.len() - 4);
+        Ok(body)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.input.read_exact(buf)?;
+        self.pos += buf.len();
+        Ok(())
+    }
+
+    fn u16_at(&self, data: &[u8], offset: usize) -> Result<u16, PcapError> {
+        let bytes = data.get(offset..offset + 2)
+            .ok_or(PcapError::Format("block too short"))?
+            .try_into().unwrap();
+
+        Ok(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32_at(&self, data: &[u8], offset: usize) -> Result<u32, PcapError> {
+        let bytes = data.get(offset..offset + 4)
+            .ok_or(PcapError::Format("block too short"))?
+            .try_into().unwrap();
+
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<CapturedPacket, PcapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_packet() {
+            Ok(Some(packet)) => Some(Ok(packet)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err @ PcapError::Parse(_)) => Some(Err(err)),
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use samsunghvac_protocol::frame::{crc16, FRAME_PREAMBLE};
+    use samsunghvac_protocol::packet::{addresses, Data, DataType, MessagesVec, PacketError, PacketInfo, PacketType};
+
+    use super::*;
+
+    fn packet(packet_number: u8) -> Packet {
+        Packet {
+            source: addresses::INDOOR,
+            destination: addresses::BROADCAST,
+            packet_info: PacketInfo::default(),
+            packet_type: PacketType::Normal,
+            data_type: DataType::Notification,
+            packet_number,
+            data: Data::Messages(MessagesVec::new()),
+        }
+    }
+
+    #[test]
+    fn round_trips_packets() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        writer.write(UNIX_EPOCH + Duration::from_secs(1), &packet(1)).unwrap();
+        writer.write(UNIX_EPOCH + Duration::from_secs(2), &packet(2)).unwrap();
+
+        let captured = PcapReader::new(writer.out.as_slice())
+            .map(Result::unwrap)
+            .map(|captured| (captured.timestamp, captured.packet.packet_number))
+            .collect::<Vec<_>>();
+
+        assert_eq!(captured, [
+            (UNIX_EPOCH + Duration::from_secs(1), 1),
+            (UNIX_EPOCH + Duration::from_secs(2), 2),
+        ]);
+    }
+
+    #[test]
+    fn packet_errors_count_from_start_of_capture() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        writer.write(UNIX_EPOCH, &packet(1)).unwrap();
+        let block_start = writer.out.len();
+        writer.write(UNIX_EPOCH, &packet(2)).unwrap();
+
+        let mut capture = writer.out;
+
+        // claim a message the second packet doesn't carry, keeping its
+        // crc valid so the frame itself still parses
+        let data_start = block_start + 8 + 20 + FRAME_PREAMBLE.len() + 3;
+        let size = u16::from_be_bytes([capture[data_start - 2], capture[data_start - 1]]);
+        let data_end = data_start + usize::from(size) - 4;
+        capture[data_start + 9] = 1;
+        let crc = crc16(&capture[data_start..data_end]);
+        capture[data_end..data_end + 2].copy_from_slice(&crc.to_be_bytes());
+
+        let mut reader = PcapReader::new(capture.as_slice());
+        assert_eq!(reader.next().unwrap().unwrap().packet.packet_number, 1);
+
+        let Some(Err(PcapError::Parse(ParseWireError::Packet(PacketError::TooShort { offset, .. })))) = reader.next() else {
+            panic!("expected the second packet to fail");
+        };
+
+        assert_eq!(offset, data_start + 10);
+        assert!(reader.next().is_none());
+    }
+}
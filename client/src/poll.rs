@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use samsunghvac_protocol::packet::{Address, MessageId};
+use tokio::time::{self, Instant};
+
+use crate::{rng, Client};
+
+/// Largest jitter accepted. At 1, an interval could shrink to nothing
+/// and the poller would spin
+const MAX_JITTER: f32 = 0.5;
+
+/// Periodically reads attributes from units that don't notify of them
+/// often enough. Results update the client's watch registry, and are
+/// passed to [`Callbacks::on_notification`](crate::Callbacks::on_notification)
+/// as if the unit had sent them.
+///
+/// Attributes due at the same time on the same unit are read together.
+pub struct Poller {
+    entries: Vec<PollEntry>,
+    jitter: f32,
+}
+
+struct PollEntry {
+    address: Address,
+    id: MessageId,
+    interval: Duration,
+    due: Option<Instant>,
+}
+
+impl Default for Poller {
+    fn default() -> Self {
+        Poller {
+            entries: Vec::new(),
+            jitter: 0.1,
+        }
+    }
+}
+
+impl Poller {
+    /// Alias for `Poller::default`
+    pub fn new() -> Self {
+        Poller::default()
+    }
+
+    /// Polls an attribute every `interval`
+    pub fn add(mut self, address: Address, id: MessageId, interval: Duration) -> Self {
+        self.entries.push(PollEntry { address, id, interval, due: None });
+        self
+    }
+
+    /// Varies each interval randomly by up to this fraction of itself, so
+    /// that polls don't fall into lockstep with each other or with other
+    /// controllers on the bus. Defaults to 0.1, and is limited to 0.5
+    pub fn jitter(mut self, fraction: f32) -> Self {
+        self.jitter = fraction.clamp(0.0, MAX_JITTER);
+        self
+    }
+
+    /// Polls forever. Spawn this alongside the client, it only ends if
+    /// dropped
+    pub async fn run(mut self, client: &Client) {
+        let now = Instant::now();
+
+        // spread first polls across their intervals, rather than all
+        // firing at once on startup
+        for entry in &mut self.entries {
+            entry.due = Some(now + entry.interval.mul_f32(rng::fraction()));
+        }
+
+        loop {
+            let Some(next) = self.entries.iter().filter_map(|entry| entry.due).min() else {
+                return std::future::pending().await;
+            };
+
+            time::sleep_until(next).await;
+            let now = Instant::now();
+
+            let mut due = HashMap::<Address, Vec<MessageId>>::new();
+
+            for entry in &mut self.entries {
+                if entry.due.is_some_and(|due| due <= now) {
+                    due.entry(entry.address).or_default().push(entry.id);
+
+                    let jitter = self.jitter * (rng::fraction() * 2.0 - 1.0);
+                    entry.due = Some(now + entry.interval.mul_f32(1.0 + jitter));
+                }
+            }
+
+            // reads of many attributes are split into packets by the client
+            for (address, ids) in due {
+                match client.read(address, &ids).await {
+                    Ok(data) => client.shared.callbacks.on_notification(address, &data),
+                    Err(err) => log::warn!("polling {address}: {err}"),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use samsunghvac_protocol::packet::{Address, Message};
+use tokio::time::Instant;
+
+use crate::Client;
+
+/// A notification kept in the replay buffer
+#[derive(Debug, Clone)]
+pub struct RecentNotification {
+    pub received_at: Instant,
+    pub messages: Vec<Message>,
+}
+
+/// Most recent notifications from each address, oldest first, up to
+/// [`ClientOptions::recent_notifications`](crate::ClientOptions::recent_notifications)
+/// per address
+pub(crate) struct RecentBuffer {
+    capacity: usize,
+    notifications: RefCell<HashMap<Address, VecDeque<RecentNotification>>>,
+}
+
+impl RecentBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RecentBuffer { capacity, notifications: Default::default() }
+    }
+
+    pub fn push(&self, address: Address, messages: &[Message]) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut notifications = self.notifications.borrow_mut();
+        let recent = notifications.entry(address).or_default();
+
+        if recent.len() == self.capacity {
+            recent.pop_front();
+        }
+
+        recent.push_back(RecentNotification {
+            received_at: Instant::now(),
+            messages: messages.to_vec(),
+        });
+    }
+
+    pub fn get(&self, address: Address) -> Vec<RecentNotification> {
+        self.notifications.borrow().get(&address)
+            .map(|recent| recent.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Client {
+    /// Recent notifications from `address`, oldest first
+    pub fn recent(&self, address: Address) -> Vec<RecentNotification> {
+        self.shared.recent.get(address)
+    }
+}
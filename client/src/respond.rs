@@ -0,0 +1,97 @@
+//! Answering requests addressed to the client, for emulating devices such
+//! as wired remotes or room sensors
+
+use std::rc::Rc;
+
+use samsunghvac_protocol::packet::{
+    Address, Data, DataType, Message, MessageId, MessagesVec, Packet, PacketInfo, PacketType, Value,
+};
+use tokio::task;
+
+use crate::{Client, Shared};
+
+/// Answers Read, Write and Request packets sent to the client's address.
+/// Register one with [`Client::set_responder`]
+pub trait Responder {
+    /// Value to answer a read of `id` from `source` with. Ids this returns
+    /// `None` for are left out of the response, and if none are known the
+    /// read is nacked
+    fn read(&self, source: Address, id: MessageId) -> Option<Value>;
+
+    /// Handles a write or request from `source`, returning whether to ack
+    /// it. By default, nacks everything
+    fn write(&self, _source: Address, _data_type: DataType, _messages: &[Message]) -> bool {
+        false
+    }
+}
+
+impl Client {
+    /// Sets the responder answering requests sent to this client. Without
+    /// one, such requests are ignored
+    pub fn set_responder(&self, responder: impl Responder + 'static) {
+        *self.shared.responder.borrow_mut() = Some(Rc::new(responder));
+    }
+
+    pub fn clear_responder(&self) {
+        *self.shared.responder.borrow_mut() = None;
+    }
+}
+
+/// Answers a request packet if a responder is registered
+pub(crate) fn on_request(shared: &Rc<Shared>, packet: &Packet, messages: &[Message]) {
+    if packet.destination != shared.options.address {
+        return;
+    }
+
+    let Some(responder) = shared.responder.borrow().clone() else {
+        return;
+    };
+
+    let (data_type, reply) = match packet.data_type {
+        DataType::Read => {
+            let reply = messages.iter()
+                .filter_map(|message| {
+                    let value = responder.read(packet.source, message.id)?;
+                    Some(Message { id: message.id, value })
+                })
+                .collect::<MessagesVec>();
+
+            if reply.is_empty() {
+                (DataType::Nack, reply)
+            } else {
+                (DataType::Response, reply)
+            }
+        }
+        DataType::Write | DataType::Request => {
+            if responder.write(packet.source, packet.data_type, messages) {
+                (DataType::Ack, MessagesVec::new())
+            } else {
+                (DataType::Nack, MessagesVec::new())
+            }
+        }
+        _ => { return; }
+    };
+
+    let reply = Packet {
+        source: shared.options.address,
+        destination: packet.source,
+        packet_info: PacketInfo::default(),
+        packet_type: PacketType::Normal,
+        data_type,
+        packet_number: packet.packet_number,
+        data: Data::Messages(reply),
+    };
+
+    log::debug!("replying {data_type} #{} to {}", reply.packet_number, reply.destination);
+
+    // replies are sent from their own task so the reader isn't held up
+    // waiting on the writer
+    let shared = shared.clone();
+    task::spawn_local(async move {
+        let mut writer = shared.writer.lock().await;
+        match writer.send(&reply).await {
+            Ok(()) => shared.metrics.update(|m| m.packets_sent += 1),
+            Err(err) => log::warn!("sending reply to {}: {err}", reply.destination),
+        }
+    });
+}
@@ -0,0 +1,148 @@
+//! Policies for resending requests that got no reply
+
+use std::fmt::Debug;
+use std::time::Duration;
+
+use crate::rng;
+
+/// Longest delay [`ExponentialBackoff`] grows to unless set otherwise
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Decides whether and when to resend a request that timed out waiting
+/// for a reply. Set for a client with
+/// [`ClientOptions::retry_policy`](crate::ClientOptions::retry_policy), or
+/// for one request with
+/// [`Client::send_with_retry_policy`](crate::Client::send_with_retry_policy)
+pub trait RetryPolicy: Debug + Send + Sync {
+    /// Delay before the next resend, after `retries` resends have already
+    /// gone unanswered. `None` gives up with
+    /// [`Error::MaxRetriesExceeded`](crate::Error::MaxRetriesExceeded)
+    fn retry_delay(&self, retries: u32) -> Option<Duration>;
+}
+
+/// Resends after the same delay each time, up to a fixed number of times.
+/// The default policy, built from
+/// [`ClientOptions::retry_delay`](crate::ClientOptions::retry_delay) and
+/// [`ClientOptions::max_retries`](crate::ClientOptions::max_retries)
+#[derive(Debug, Clone)]
+pub struct FixedDelay {
+    pub delay: Duration,
+    pub max_retries: u32,
+}
+
+impl RetryPolicy for FixedDelay {
+    fn retry_delay(&self, retries: u32) -> Option<Duration> {
+        (retries < self.max_retries).then_some(self.delay)
+    }
+}
+
+/// Resends after a delay that grows by `multiplier` each time, up to
+/// `max_delay`. With jitter, each delay is shortened by a random fraction
+/// of up to `jitter`, so that controllers which timed out together don't
+/// all retry together
+#[derive(Debug, Clone)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    multiplier: f64,
+    max_delay: Duration,
+    max_retries: u32,
+    jitter: f64,
+}
+
+impl ExponentialBackoff {
+    /// Backoff starting at `initial` and doubling each retry up to 30
+    /// seconds, with no jitter
+    pub fn new(initial: Duration, max_retries: u32) -> Self {
+        ExponentialBackoff {
+            initial,
+            multiplier: 2.0,
+            max_delay: DEFAULT_MAX_DELAY,
+            max_retries,
+            jitter: 0.0,
+        }
+    }
+
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Largest fraction of each delay to randomly take off it, from 0 to 1
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn retry_delay(&self, retries: u32) -> Option<Duration> {
+        if retries >= self.max_retries {
+            return None;
+        }
+
+        let factor = self.multiplier.powi(retries.try_into().unwrap_or(i32::MAX));
+        let delay = self.initial.as_secs_f64() * factor;
+        let delay = delay * (1.0 - self.jitter * f64::from(rng::fraction()));
+
+        let delay = Duration::try_from_secs_f64(delay).unwrap_or(Duration::MAX);
+        Some(delay.min(self.max_delay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delays(policy: &impl RetryPolicy) -> Vec<Option<Duration>> {
+        (0..5).map(|retries| policy.retry_delay(retries)).collect()
+    }
+
+    #[test]
+    fn fixed_delay() {
+        let policy = FixedDelay { delay: Duration::from_millis(100), max_retries: 2 };
+        let delay = Some(Duration::from_millis(100));
+        assert_eq!(delays(&policy), [delay, delay, None, None, None]);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_retries() {
+        let policy = ExponentialBackoff::new(Duration::from_millis(100), 4);
+
+        assert_eq!(delays(&policy), [
+            Some(Duration::from_millis(100)),
+            Some(Duration::from_millis(200)),
+            Some(Duration::from_millis(400)),
+            Some(Duration::from_millis(800)),
+            None,
+        ]);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = ExponentialBackoff::new(Duration::from_secs(1), 100)
+            .multiplier(10.0);
+
+        assert_eq!(policy.retry_delay(1), Some(Duration::from_secs(10)));
+        assert_eq!(policy.retry_delay(2), Some(DEFAULT_MAX_DELAY));
+        assert_eq!(policy.retry_delay(99), Some(DEFAULT_MAX_DELAY));
+
+        let policy = policy.max_delay(Duration::from_secs(5));
+        assert_eq!(policy.retry_delay(1), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn backoff_jitter_only_shortens() {
+        let policy = ExponentialBackoff::new(Duration::from_secs(1), 10)
+            .jitter(0.5);
+
+        for _ in 0..100 {
+            let delay = policy.retry_delay(1).unwrap();
+            assert!(delay > Duration::from_secs(1) && delay <= Duration::from_secs(2), "{delay:?}");
+        }
+    }
+}
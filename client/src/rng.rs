@@ -0,0 +1,29 @@
+//! Enough randomness for jitter, without a dependency
+
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+fn seed() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+
+    // xorshift state must be non-zero
+    nanos | 1
+}
+
+/// Uniform in `[0, 1)`
+pub(crate) fn fraction() -> f32 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        (x >> 40) as f32 / (1u64 << 24) as f32
+    })
+}
@@ -0,0 +1,163 @@
+//! A [`Send`] + [`Sync`] handle to a [`Client`], for use from multi-threaded
+//! runtimes
+//!
+//! [`Client`] itself is built on `Rc` and `spawn_local`. Rather than
+//! duplicating it, [`ThreadedClient`] runs one on a dedicated thread with
+//! its own current-thread runtime, and forwards calls to it over a channel.
+//! Calls are handled concurrently, just as on the client itself.
+
+use std::io;
+use std::rc::Rc;
+use std::thread;
+
+use samsunghvac_protocol::packet::{Address, Message, MessageId, Structure};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{self, LocalSet};
+
+use crate::message::MessageSet;
+use crate::transport::{OpenError, TransportOpt};
+use crate::{Callbacks, Client, ClientOptions, Error};
+
+/// Cloneable handle to a client running on its own thread. The thread
+/// exits once every handle has been dropped
+#[derive(Clone)]
+pub struct ThreadedClient {
+    tx: mpsc::UnboundedSender<Call>,
+}
+
+// the whole point of the handle, keep it that way:
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ThreadedClient>();
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConnectError {
+    #[error(transparent)]
+    Open(#[from] OpenError),
+    #[error("starting client thread: {0}")]
+    Thread(#[source] io::Error),
+    #[error("client thread exited before connecting")]
+    ThreadExited,
+}
+
+enum Call {
+    Read {
+        address: Address,
+        attrs: Vec<MessageId>,
+        reply: oneshot::Sender<Result<MessageSet<'static>, Error>>,
+    },
+    ReadStructure {
+        address: Address,
+        id: MessageId,
+        reply: oneshot::Sender<Result<Structure, Error>>,
+    },
+    Request {
+        address: Address,
+        messages: Vec<Message>,
+        reply: oneshot::Sender<Result<(), Error>>,
+    },
+    Write {
+        address: Address,
+        messages: Vec<Message>,
+        reply: oneshot::Sender<Result<(), Error>>,
+    },
+}
+
+impl ThreadedClient {
+    /// Connects a client on a new thread. Callbacks are called on that
+    /// thread
+    pub async fn connect(opt: TransportOpt, options: ClientOptions, callbacks: impl Callbacks + Send + 'static)
+        -> Result<Self, ConnectError>
+    {
+        let (connected_tx, connected_rx) = oneshot::channel();
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        thread::Builder::new()
+            .name("samsunghvac-client".to_owned())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build();
+
+                let runtime = match runtime {
+                    Ok(runtime) => runtime,
+                    Err(err) => {
+                        let _: Result<_, _> = connected_tx.send(Err(ConnectError::Thread(err)));
+                        return;
+                    }
+                };
+
+                LocalSet::new().block_on(&runtime, async move {
+                    match Client::connect(&opt, options, callbacks).await {
+                        Ok(client) => {
+                            let _: Result<_, _> = connected_tx.send(Ok(()));
+                            serve(Rc::new(client), rx).await;
+                        }
+                        Err(err) => {
+                            let _: Result<_, _> = connected_tx.send(Err(err.into()));
+                        }
+                    }
+                });
+            })
+            .map_err(ConnectError::Thread)?;
+
+        // the sender only goes unused if the thread panicked
+        connected_rx.await.map_err(|_| ConnectError::ThreadExited)??;
+        Ok(ThreadedClient { tx })
+    }
+
+    pub async fn read(&self, address: Address, attrs: &[MessageId]) -> Result<MessageSet<'static>, Error> {
+        let attrs = attrs.to_vec();
+        self.call(|reply| Call::Read { address, attrs, reply }).await
+    }
+
+    pub async fn read_structure(&self, address: Address, id: MessageId) -> Result<Structure, Error> {
+        self.call(|reply| Call::ReadStructure { address, id, reply }).await
+    }
+
+    pub async fn request(&self, address: Address, messages: &[Message]) -> Result<(), Error> {
+        let messages = messages.to_vec();
+        self.call(|reply| Call::Request { address, messages, reply }).await
+    }
+
+    pub async fn write(&self, address: Address, messages: &[Message]) -> Result<(), Error> {
+        let messages = messages.to_vec();
+        self.call(|reply| Call::Write { address, messages, reply }).await
+    }
+
+    async fn call<T>(&self, call: impl FnOnce(oneshot::Sender<Result<T, Error>>) -> Call)
+        -> Result<T, Error>
+    {
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        // the client thread only goes away if its runtime does
+        self.tx.send(call(reply_tx)).map_err(|_| Error::LostTransport)?;
+        reply_rx.await.map_err(|_| Error::LostTransport)?
+    }
+}
+
+async fn serve(client: Rc<Client>, mut rx: mpsc::UnboundedReceiver<Call>) {
+    while let Some(call) = rx.recv().await {
+        let client = client.clone();
+
+        task::spawn_local(async move {
+            match call {
+                Call::Read { address, attrs, reply } => {
+                    let result = client.read(address, &attrs).await
+                        .map(|set| MessageSet::from_vec(set.messages().to_vec()));
+                    let _: Result<_, _> = reply.send(result);
+                }
+                Call::ReadStructure { address, id, reply } => {
+                    let _: Result<_, _> = reply.send(client.read_structure(address, id).await);
+                }
+                Call::Request { address, messages, reply } => {
+                    let _: Result<_, _> = reply.send(client.request(address, &messages).await);
+                }
+                Call::Write { address, messages, reply } => {
+                    let _: Result<_, _> = reply.send(client.write(address, &messages).await);
+                }
+            }
+        });
+    }
+}
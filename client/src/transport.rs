@@ -1,25 +1,32 @@
 use std::io;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::process::Stdio;
 use std::sync::LazyLock;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
 
-use async_stream::try_stream;
-use bytes::{Bytes, BytesMut};
+use bytes::Bytes;
 use futures::{Stream, StreamExt};
-use samsunghvac_protocol::frame::{FrameError, FrameParser, MAX_FRAME_SIZE};
-use samsunghvac_protocol::packet::{Packet, PacketError, SerializePacketError};
+use samsunghvac_protocol::packet::{addresses, Packet, PacketMeta, SerializePacketError};
+pub use samsunghvac_protocol::packet::ReadPacketError;
 use samsunghvac_protocol::pretty::pretty_print;
 use structopt::StructOpt;
 use thiserror::Error;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::net::UnixStream;
 use tokio_serial::SerialPortBuilderExt;
+use crate::codec::{FrameStream, NasaCodec};
 
 const BAUD_RATE: u32 = 9600;
+const PAIR_BUFFER: usize = 4096;
+const EXEC_PREFIX: &str = "exec:";
 
-#[derive(StructOpt)]
+#[derive(StructOpt, Clone)]
 pub struct TransportOpt {
+    /// Path to the busd socket or a serial port, or `exec:` followed by a
+    /// shell command to exchange frames with over its stdin and stdout,
+    /// eg. `exec:ssh pi socat - UNIX-CONNECT:/var/run/samsunghvac/bus`
     #[structopt(long = "bus", env = "SAMSUNGHVAC_BUS", default_value_os = DEFAULT_SOCKET.as_os_str())]
     pub bus: PathBuf,
 }
@@ -33,7 +40,19 @@ pub fn new(io: impl AsyncRead + AsyncWrite + Send + 'static) -> AsyncTransport {
     (rd, wr)
 }
 
+/// Creates two transports connected to each other in memory, for testing
+/// code built on [`Client`](crate::Client) without a bus
+pub fn pair() -> (AsyncTransport, AsyncTransport) {
+    let (a, b) = tokio::io::duplex(PAIR_BUFFER);
+    (new(a), new(b))
+}
+
 pub async fn open(opt: &TransportOpt) -> Result<AsyncTransport, OpenError> {
+    if let Some(command) = opt.bus.to_str().and_then(|bus| bus.strip_prefix(EXEC_PREFIX)) {
+        return open_exec(command)
+            .map_err(|error| OpenError { path: opt.bus.to_owned(), error });
+    }
+
     match open_unix_socket(&opt.bus).await {
         Ok(Some(io)) => { return Ok(io); }
         Ok(None) => {}
@@ -42,12 +61,8 @@ pub async fn open(opt: &TransportOpt) -> Result<AsyncTransport, OpenError> {
         }
     }
 
-    match open_serial_port(&opt.bus).await {
-        Ok(io) => { return Ok(io); }
-        Err(error) => {
-            return Err(OpenError { path: opt.bus.to_owned(), error: error.into() });
-        }
-    }
+    open_serial_port(&opt.bus).await
+        .map_err(|error| OpenError { path: opt.bus.to_owned(), error: error.into() })
 }
 
 #[derive(Debug, Error)]
@@ -60,25 +75,47 @@ pub struct OpenError {
 
 pub struct TransportReceiver {
     rd: Pin<Box<dyn Stream<Item = PacketStreamResult> + Send>>,
+    id: u32,
 }
 
-#[derive(Error, Debug)]
-pub enum ReadPacketError {
-    #[error(transparent)]
-    Frame(#[from] FrameError),
-    #[error(transparent)]
-    Packet(#[from] PacketError),
+static NEXT_TRANSPORT_ID: AtomicU32 = AtomicU32::new(0);
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Monotonic time since an arbitrary process-wide epoch, as used for
+/// [`PacketMeta::received_at`]
+pub fn monotonic_now() -> Duration {
+    EPOCH.elapsed()
 }
 
 impl TransportReceiver {
     pub fn new(rd: impl AsyncRead + Send + 'static) -> Self {
+        TransportReceiver::with_codec(rd, NasaCodec::new())
+    }
+
+    /// Like [`TransportReceiver::new`], decoding with `codec`, eg. to
+    /// change its frame timeout
+    pub fn with_codec(rd: impl AsyncRead + Send + 'static, codec: NasaCodec) -> Self {
+        let id = NEXT_TRANSPORT_ID.fetch_add(1, Ordering::Relaxed);
+
         // monomorphise before calling packet_stream:
         let rd = Box::pin(rd) as Pin<Box<dyn AsyncRead + Send + 'static>>;
-        let rd = Box::pin(packet_stream(rd)) as Pin<Box<_>>;
-        TransportReceiver { rd }
+        let rd = Box::pin(packet_stream(rd, codec, id)) as Pin<Box<_>>;
+        TransportReceiver { rd, id }
+    }
+
+    /// Process-unique id of this transport, as reported in [`PacketMeta`]
+    pub fn id(&self) -> u32 {
+        self.id
     }
 
     pub async fn try_read(&mut self) -> Result<Result<Box<Packet>, ReadPacketError>, io::Error> {
+        let result = self.try_read_with_meta().await?;
+        Ok(result.map(|(packet, _)| packet))
+    }
+
+    pub async fn try_read_with_meta(&mut self)
+        -> Result<Result<(Box<Packet>, PacketMeta), ReadPacketError>, io::Error>
+    {
         self.rd.next().await.ok_or(io::Error::new(
             io::ErrorKind::UnexpectedEof,
             "transport receiver stream ended",
@@ -86,18 +123,30 @@ impl TransportReceiver {
     }
 
     pub async fn read(&mut self) -> Result<Box<Packet>, io::Error> {
+        let (packet, _) = self.read_with_meta().await?;
+        Ok(packet)
+    }
+
+    pub async fn read_with_meta(&mut self) -> Result<(Box<Packet>, PacketMeta), io::Error> {
+        self.read_reporting(|_| {}).await
+    }
+
+    /// Like [`TransportReceiver::read_with_meta`], calling `on_error` with
+    /// each frame or packet skipped for failing to parse
+    pub async fn read_reporting(&mut self, mut on_error: impl FnMut(&ReadPacketError))
+        -> Result<(Box<Packet>, PacketMeta), io::Error>
+    {
         loop {
-            match self.try_read().await? {
-                Ok(packet) => {
-                    if packet.source.class != 0x10 {
-                        let mut pretty = String::new();
-                        pretty_print(&mut pretty, &packet, true).unwrap();
-                        log::debug!("recv packet: {pretty}");
+            match self.try_read_with_meta().await? {
+                Ok((packet, meta)) => {
+                    if packet.source.class != addresses::class::OUTDOOR {
+                        log_packet("recv", &packet);
                     }
-                    return Ok(packet);
+                    return Ok((packet, meta));
                 }
                 Err(err) => {
                     log::warn!("read: {err}");
+                    on_error(&err);
                 }
             }
         }
@@ -123,9 +172,7 @@ impl TransportSender {
     }
 
     pub async fn send(&mut self, packet: &Packet) -> Result<(), SendPacketError> {
-        let mut pretty = String::new();
-        pretty_print(&mut pretty, &packet, true).unwrap();
-        log::debug!("send packet: {pretty}");
+        log_packet("send", packet);
 
         let bytes = serialize_frame(packet)?;
         self.wr.write_all(&bytes).await?;
@@ -133,50 +180,36 @@ impl TransportSender {
     }
 }
 
+/// Pretty prints packets at debug level. Formatting is skipped entirely
+/// unless debug logging is enabled, and compiled out without the
+/// `packet-log` feature.
+fn log_packet(direction: &str, packet: &Packet) {
+    if cfg!(feature = "packet-log") && log::log_enabled!(log::Level::Debug) {
+        let mut pretty = String::new();
+        pretty_print(&mut pretty, packet, true).unwrap();
+        log::debug!("{direction} packet: {pretty}");
+    }
+}
+
 fn serialize_frame(packet: &Packet) -> Result<Bytes, SerializePacketError> {
-    let mut bytes = BytesMut::zeroed(MAX_FRAME_SIZE);
-    let n = packet.serialize_frame(&mut bytes)?;
-    bytes.truncate(n);
-    Ok(bytes.into())
+    Ok(packet.serialize_to_vec()?.into())
 }
 
-type PacketStreamResult = io::Result<Result<Box<Packet>, ReadPacketError>>;
+type PacketStreamResult = io::Result<Result<(Box<Packet>, PacketMeta), ReadPacketError>>;
 
-fn packet_stream(mut io: Pin<Box<dyn AsyncRead + Send>>)
+fn packet_stream(io: Pin<Box<dyn AsyncRead + Send>>, codec: NasaCodec, transport_id: u32)
     -> impl Stream<Item = PacketStreamResult>
 {
-    try_stream! {
-        let mut parser = FrameParser::new();
-        let mut buffer = [0u8; 1024];
-
-        loop {
-            let data = match io.read(&mut buffer).await? {
-                0 => break,
-                n => &buffer[..n],
+    FrameStream::new(io, codec).map(move |result| {
+        result.map(|result| result.map(|packet| {
+            let meta = PacketMeta {
+                received_at: Some(monotonic_now()),
+                transport_id: Some(transport_id),
             };
 
-            for byte in data {
-                let frame = match parser.feed(*byte) {
-                    Ok(None) => continue,
-                    Ok(Some(frame)) => frame,
-                    Err(err) => {
-                        yield Err(err.into());
-                        continue;
-                    }
-                };
-
-                let packet = match Packet::parse(frame) {
-                    Ok(packet) => packet,
-                    Err(err) => {
-                        yield Err(err.into());
-                        continue;
-                    }
-                };
-
-                yield Ok(Box::new(packet));
-            }
-        }
-    }
+            (packet, meta)
+        }))
+    })
 }
 
 async fn open_unix_socket(path: &Path) -> Result<Option<AsyncTransport>, io::Error> {
@@ -192,6 +225,34 @@ async fn open_unix_socket(path: &Path) -> Result<Option<AsyncTransport>, io::Err
     Ok(Some(new(stream)))
 }
 
+/// Spawns a shell command, speaking frames over its stdin and stdout. Its
+/// stderr is left attached to ours. Dropping the transport closes its
+/// stdin, and it's killed if still running when the runtime shuts down
+fn open_exec(command: &str) -> Result<AsyncTransport, io::Error> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let stdin = child.stdin.take().expect("child stdin is piped");
+    let stdout = child.stdout.take().expect("child stdout is piped");
+
+    // wait on the child so it's reaped when it exits. its stdout closing
+    // is what ends the transport
+    let command = command.to_owned();
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) => log::info!("{command}: exited with {status}"),
+            Err(err) => log::warn!("{command}: waiting on child: {err}"),
+        }
+    });
+
+    Ok(new(tokio::io::join(stdout, stdin)))
+}
+
 async fn open_serial_port(path: &Path) -> Result<AsyncTransport, tokio_serial::Error> {
     let path = path.to_string_lossy();
 
@@ -209,6 +270,11 @@ pub static DEFAULT_SOCKET: LazyLock<PathBuf> = LazyLock::new(|| {
     runtime_dir().join("bus")
 });
 
+/// Where busd serves its admin interface, for busd and busdctl to agree on
+pub static DEFAULT_ADMIN_SOCKET: LazyLock<PathBuf> = LazyLock::new(|| {
+    runtime_dir().join("admin")
+});
+
 pub fn runtime_dir() -> PathBuf {
     if let Some(dir) = std::env::var_os("RUNTIME_DIRECTORY") {
         PathBuf::from(dir)
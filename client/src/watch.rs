@@ -0,0 +1,94 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use samsunghvac_protocol::message::convert::ValueType;
+use samsunghvac_protocol::message::IsMessage;
+use samsunghvac_protocol::packet::{Address, Message, MessageId, Value};
+use tokio::sync::watch;
+
+/// Tracks the latest known values of watched attributes, kept up to date
+/// from notifications and read responses
+#[derive(Default)]
+pub struct WatchRegistry {
+    values: RefCell<HashMap<(Address, MessageId), ValueSender>>,
+}
+
+type ValueSender = watch::Sender<Option<Value>>;
+
+impl WatchRegistry {
+    pub fn new() -> Self {
+        WatchRegistry::default()
+    }
+
+    /// Starts watching an attribute. Has no effect if already watched
+    pub fn watch(&self, address: Address, id: MessageId) {
+        self.values.borrow_mut().entry((address, id))
+            .or_insert_with(|| watch::Sender::new(None));
+    }
+
+    /// Stops watching an attribute, ending any [`Watch`] subscribed to it
+    pub fn unwatch(&self, address: Address, id: MessageId) {
+        self.values.borrow_mut().remove(&(address, id));
+    }
+
+    pub fn is_watched(&self, address: Address, id: MessageId) -> bool {
+        self.values.borrow().contains_key(&(address, id))
+    }
+
+    /// Latest known value of a watched attribute, if any has been seen
+    pub fn get(&self, address: Address, id: MessageId) -> Option<Value> {
+        self.values.borrow().get(&(address, id)).and_then(|tx| *tx.borrow())
+    }
+
+    /// Watches an attribute, returning a typed subscription to its changes
+    pub fn subscribe<M: IsMessage>(&self, address: Address) -> Watch<M> {
+        self.watch(address, M::ID);
+
+        let rx = self.values.borrow()[&(address, M::ID)].subscribe();
+        Watch { rx, _message: PhantomData }
+    }
+
+    /// Updates watched attributes from messages sent by `address`.
+    /// Messages for attributes not being watched are ignored
+    pub fn update(&self, address: Address, messages: &[Message]) {
+        let values = self.values.borrow();
+
+        for message in messages {
+            if let Some(tx) = values.get(&(address, message.id)) {
+                tx.send_if_modified(|value| {
+                    let changed = *value != Some(message.value);
+                    *value = Some(message.value);
+                    changed
+                });
+            }
+        }
+    }
+}
+
+/// Typed subscription to a watched attribute
+pub struct Watch<M> {
+    rx: watch::Receiver<Option<Value>>,
+    _message: PhantomData<fn() -> M>,
+}
+
+impl<M: IsMessage> Watch<M> {
+    /// Latest known value, if any has been seen
+    pub fn get(&self) -> Option<M::Value> {
+        let value = (*self.rx.borrow())?;
+        M::Value::try_from_value(value)
+    }
+
+    /// Waits for the value to change, returning the new value. Returns
+    /// `None` once the attribute is unwatched or the client dropped
+    pub async fn changed(&mut self) -> Option<M::Value> {
+        loop {
+            self.rx.changed().await.ok()?;
+
+            // values that don't decode as M::Value are skipped
+            if let Some(value) = self.get() {
+                return Some(value);
+            }
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use std::collections::HashMap;
 use std::{fmt, io};
+use std::path::PathBuf;
 use std::process::ExitCode;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::fmt::{Display, Write};
 
 use axum::extract::State;
@@ -9,7 +12,8 @@ use axum::Router;
 use futures::future;
 use samsunghvac_protocol::message;
 use samsunghvac_protocol::message::convert::{IsMessage, ValueType};
-use samsunghvac_protocol::packet::{Address, Data, DataType, MessageId, Packet, PacketType, Value};
+use samsunghvac_protocol::message::types::{Celsius, TempScale, ThermoState, UnknownOr};
+use samsunghvac_protocol::packet::{Address, DataType, MessageId, Packet, PacketType, Value};
 use structopt::StructOpt;
 use thiserror::Error;
 
@@ -19,19 +23,49 @@ use samsunghvac_client::transport::{self, TransportOpt, TransportReceiver};
 struct Opt {
     #[structopt(flatten)]
     pub transport: TransportOpt,
+    /// Name of the bus given by --bus, used as the bus label on its series
+    #[structopt(long = "bus-name", default_value = "default")]
+    pub bus_name: String,
+    /// Additional bus to export from, as NAME=PATH. May be given multiple times
+    #[structopt(long = "extra-bus")]
+    pub extra_buses: Vec<NamedBus>,
     #[structopt(short = "l", long = "listen", default_value = "0.0.0.0:8000")]
     pub listen: String,
+    /// Address of a unit that sends temperatures in fahrenheit, exported
+    /// in celsius like the rest. May be given multiple times
+    #[structopt(long = "fahrenheit")]
+    pub fahrenheit: Vec<Address>,
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), ExitCode> {
-    let opt = Opt::from_args();
-    samsunghvac_common::log::init();
+struct NamedBus {
+    name: String,
+    transport: TransportOpt,
+}
+
+#[derive(Error, Debug)]
+#[error("expected bus in the form NAME=PATH")]
+struct InvalidNamedBus;
+
+impl FromStr for NamedBus {
+    type Err = InvalidNamedBus;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, path) = s.split_once('=').ok_or(InvalidNamedBus)?;
 
-    run(opt).await.map_err(|err| {
-        log::error!("{err}");
-        ExitCode::FAILURE
-    })
+        if name.is_empty() || path.is_empty() {
+            return Err(InvalidNamedBus);
+        }
+
+        Ok(NamedBus {
+            name: name.to_owned(),
+            transport: TransportOpt { bus: PathBuf::from(path) },
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    let opt = Opt::from_args();
+    samsunghvac_app::run(run(opt))
 }
 
 #[derive(Error, Debug)]
@@ -40,30 +74,61 @@ enum RunError {
     Bind(#[source] io::Error, String),
     #[error(transparent)]
     OpenBus(#[from] transport::OpenError),
-    #[error("bus i/o: {0}")]
-    RunBus(#[source] io::Error),
+    #[error("bus {1} i/o: {0}")]
+    RunBus(#[source] io::Error, String),
     #[error("serving metrics: {0}")]
     RunHttp(#[source] io::Error)
 }
 
-#[derive(Default)]
 struct AppState {
+    buses: Vec<(String, Arc<BusState>)>,
+    fahrenheit: Vec<Address>,
+}
+
+#[derive(Default)]
+struct BusState {
     metrics: Mutex<HashMap<Address, AttrMap>>,
+    commands: Mutex<HashMap<CommandPair, CommandStats>>,
 }
 
 type AttrMap = HashMap<MessageId, Value>;
 
+/// Source and destination of commands observed on the bus
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+struct CommandPair {
+    source: Address,
+    destination: Address,
+}
+
+#[derive(Default)]
+struct CommandStats {
+    requests: u64,
+    writes: u64,
+    last_seen: Option<SystemTime>,
+}
+
 async fn run(opt: Opt) -> Result<(), RunError> {
-    let state = Arc::new(AppState::default());
+    let primary = NamedBus { name: opt.bus_name, transport: opt.transport };
 
-    let (bus, _) = transport::open(&opt.transport).await?;
+    let mut buses = Vec::new();
+    let mut tasks = Vec::new();
 
-    let bus_task = tokio::task::spawn({
-        let state = state.clone();
-        async move {
-            run_bus(bus, state).await.map_err(RunError::RunBus)
-        }
-    });
+    for named in [primary].into_iter().chain(opt.extra_buses) {
+        let (bus, _) = transport::open(&named.transport).await?;
+        let state = Arc::new(BusState::default());
+
+        let name = named.name.clone();
+        tasks.push(tokio::task::spawn({
+            let state = state.clone();
+            async move {
+                run_bus(bus, state).await.map_err(|e| RunError::RunBus(e, name))
+            }
+        }));
+
+        buses.push((named.name, state));
+    }
+
+    let state = Arc::new(AppState { buses, fahrenheit: opt.fahrenheit });
 
     let app = Router::new()
         .route("/metrics", axum::routing::get(metrics))
@@ -72,37 +137,55 @@ async fn run(opt: Opt) -> Result<(), RunError> {
     let listener = tokio::net::TcpListener::bind(&opt.listen).await
         .map_err(|e| RunError::Bind(e, opt.listen))?;
 
-    let http_task = tokio::task::spawn(async move {
+    tasks.push(tokio::task::spawn(async move {
         axum::serve(listener, app).await.map_err(RunError::RunHttp)
-    });
+    }));
 
-    let (result, _) = future::select(bus_task, http_task).await.factor_first();
+    let (result, _, _) = future::select_all(tasks).await;
     result.unwrap()
 }
 
-async fn run_bus(mut bus: TransportReceiver, state: Arc<AppState>) -> Result<(), io::Error> {
+async fn run_bus(mut bus: TransportReceiver, state: Arc<BusState>) -> Result<(), io::Error> {
     loop {
         let packet = bus.read().await?;
         on_packet(&packet, &state);
     }
 }
 
-fn on_packet(packet: &Packet, state: &AppState) {
+fn on_packet(packet: &Packet, state: &BusState) {
     if packet.packet_type != PacketType::Normal {
         return;
     }
 
-    if packet.data_type != DataType::Notification {
-        return;
+    match packet.data_type {
+        DataType::Notification => on_notification(packet, state),
+        DataType::Request | DataType::Write => on_command(packet, state),
+        _ => {}
     }
+}
 
-    let Data::Messages(msgs) = &packet.data else {
-        return;
+fn on_command(packet: &Packet, state: &BusState) {
+    let pair = CommandPair {
+        source: packet.source,
+        destination: packet.destination,
     };
 
+    let mut commands = state.commands.lock().unwrap();
+    let stats = commands.entry(pair).or_default();
+
+    match packet.data_type {
+        DataType::Request => stats.requests += 1,
+        DataType::Write => stats.writes += 1,
+        _ => {}
+    }
+
+    stats.last_seen = Some(SystemTime::now());
+}
+
+fn on_notification(packet: &Packet, state: &BusState) {
     let mut metrics = state.metrics.lock().unwrap();
 
-    for msg in msgs {
+    for msg in &packet.data {
         metrics.entry(packet.source)
             .or_default()
             .insert(msg.id, msg.value);
@@ -113,65 +196,197 @@ async fn metrics(state: State<Arc<AppState>>) -> Result<String, ()> {
     render_metrics(&state).map_err(|_| ())
 }
 
-fn render_metrics(state: &AppState) -> Result<String, fmt::Error> {
+fn render_metrics(app: &AppState) -> Result<String, fmt::Error> {
     let mut out = String::new();
 
+    for (bus, state) in &app.buses {
+        render_bus(&mut out, bus, state, &app.fahrenheit)?;
+    }
+
+    Ok(out)
+}
+
+fn render_bus(out: &mut String, bus: &str, state: &BusState, fahrenheit: &[Address]) -> fmt::Result {
     let metrics = state.metrics.lock().unwrap();
 
     for (address, attrs) in metrics.iter() {
-        let m = AddressMetrics { out: &mut out, address: *address };
+        let scale = match fahrenheit.contains(address) {
+            true => TempScale::Fahrenheit,
+            false => TempScale::Celsius,
+        };
+
+        let m = AddressMetrics { out, bus, address: *address, scale };
         render_attributes(m, attrs)?;
     }
 
-    Ok(out)
+    drop(metrics);
+
+    let commands = state.commands.lock().unwrap();
+
+    for (pair, stats) in commands.iter() {
+        render_commands(out, bus, pair, stats)?;
+    }
+
+    Ok(())
+}
+
+fn render_commands(out: &mut String, bus: &str, pair: &CommandPair, stats: &CommandStats) -> fmt::Result {
+    let labels = format!("bus=\"{bus}\",source=\"{}\",destination=\"{}\"", pair.source, pair.destination);
+
+    writeln!(out, "samsung_hvac_commands_total{{{labels},type=\"request\"}} {}", stats.requests)?;
+    writeln!(out, "samsung_hvac_commands_total{{{labels},type=\"write\"}} {}", stats.writes)?;
+
+    let last_seen = stats.last_seen
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+
+    if let Some(last_seen) = last_seen {
+        writeln!(out, "samsung_hvac_last_command_timestamp_seconds{{{labels}}} {}",
+            last_seen.as_secs_f64())?;
+    }
+
+    Ok(())
 }
 
 fn render_attributes(mut m: AddressMetrics, attrs: &AttrMap) -> fmt::Result {
-    if let Some(temp) = get_message::<message::SetTemp>(&attrs) {
-        m.gauge("set_temperature_celsius", temp.as_float())?;
+    if let Some(temp) = get_message::<message::SetTemp>(attrs) {
+        m.gauge("set_temperature_celsius", m.celsius(temp))?;
+    }
+
+    if let Some(temp) = get_message::<message::CurrentTemp>(attrs) {
+        m.gauge("current_temperature_celsius", m.celsius(temp))?;
+    }
+
+    if let Some(temp) = get_message::<message::EvaInTemp>(attrs) {
+        m.gauge("coil_inlet_temperature_celsius", m.celsius(temp))?;
     }
 
-    if let Some(temp) = get_message::<message::CurrentTemp>(&attrs) {
-        m.gauge("current_temperature_celsius", temp.as_float())?;
+    if let Some(temp) = get_message::<message::EvaOutTemp>(attrs) {
+        m.gauge("coil_outlet_temperature_celsius", m.celsius(temp))?;
     }
 
-    if let Some(temp) = get_message::<message::EvaInTemp>(&attrs) {
-        m.gauge("coil_inlet_temperature_celsius", temp.as_float())?;
+    if let Some(temp) = get_message::<message::OutdoorTemp>(attrs) {
+        m.gauge("outdoor_temperature_celsius", m.celsius(temp))?;
     }
 
-    if let Some(temp) = get_message::<message::EvaOutTemp>(&attrs) {
-        m.gauge("coil_outlet_temperature_celsius", temp.as_float())?;
+    if let Some(temp) = get_message::<message::OutdoorDischargeTemp>(attrs) {
+        m.gauge("outdoor_discharge_temperature_celsius", m.celsius(temp))?;
     }
 
-    if let Some(temp) = get_message::<message::OutdoorTemp>(&attrs) {
-        m.gauge("outdoor_temperature_celsius", temp.as_float())?;
+    if let Some(temp) = get_message::<message::OutdoorExchangerTemp>(attrs) {
+        m.gauge("outdoor_exchanger_temperature_celsius", m.celsius(temp))?;
     }
 
-    if let Some(temp) = get_message::<message::OutdoorDischargeTemp>(&attrs) {
-        m.gauge("outdoor_discharge_temperature_celsius", temp.as_float())?;
+    if let Some(temp) = get_message::<message::WaterInletTemp>(attrs) {
+        m.gauge("water_inlet_temperature_celsius", m.celsius(temp))?;
     }
 
-    if let Some(temp) = get_message::<message::OutdoorExchangerTemp>(&attrs) {
-        m.gauge("outdoor_exchanger_temperature_celsius", temp.as_float())?;
+    if let Some(temp) = get_message::<message::WaterOutletTemp>(attrs) {
+        m.gauge("water_outlet_temperature_celsius", m.celsius(temp))?;
     }
 
+    if let Some(temp) = get_message::<message::WaterOutlet2Temp>(attrs) {
+        m.gauge("water_outlet2_temperature_celsius", m.celsius(temp))?;
+    }
+
+    if let Some(temp) = get_message::<message::WaterTankTemp>(attrs) {
+        m.gauge("water_tank_temperature_celsius", m.celsius(temp))?;
+    }
+
+    if let Some(temp) = get_message::<message::WaterOutletTargetTemp>(attrs) {
+        m.gauge("water_outlet_target_temperature_celsius", m.celsius(temp))?;
+    }
+
+    if let Some(temp) = get_message::<message::WaterLawTargetTemp>(attrs) {
+        m.gauge("water_law_target_temperature_celsius", m.celsius(temp))?;
+    }
+
+    render_water_law(&mut m, attrs)?;
+    render_status(&mut m, attrs)?;
+
     // render raw notification values
     for (message, value) in attrs.iter() {
-        let int = match *value {
-            Value::Enum(i) => u32::from(i),
-            Value::Variable(i) => u32::from(i),
-            Value::LongVariable(i) => i,
+        let int = match message.metadata() {
+            Some(meta) => meta.integer(*value),
+            None => i64::from(value.as_u32()),
         };
 
         writeln!(&mut m.out,
-            "samsung_hvac_notification_value{{address=\"{address}\",message=\"{message}\"}} {int}",
+            "samsung_hvac_notification_value{{bus=\"{bus}\",address=\"{address}\",message=\"{message}\"}} {int}",
+            bus = m.bus,
+            address = m.address,
+        )?;
+
+        // render scaled physical values for messages known to the catalog
+        let Some(meta) = message.metadata() else { continue };
+        let Some(scaled) = meta.scaled_in(*value, m.scale) else { continue };
+
+        writeln!(&mut m.out,
+            "samsung_hvac_scaled_value{{bus=\"{bus}\",address=\"{address}\",message=\"{message}\",name=\"{name}\",unit=\"{unit}\"}} {scaled}",
+            bus = m.bus,
             address = m.address,
+            name = meta.name,
+            unit = meta.unit.unwrap_or(""),
         )?;
     }
 
     Ok(())
 }
 
+fn render_water_law(m: &mut AddressMetrics, attrs: &AttrMap) -> fmt::Result {
+    let points = [
+        ("heat", "outdoor_max", get_message::<message::WaterLawHeatOutdoorMax>(attrs)),
+        ("heat", "outdoor_min", get_message::<message::WaterLawHeatOutdoorMin>(attrs)),
+        ("heat", "wl1_max", get_message::<message::WaterLawHeatWl1Max>(attrs)),
+        ("heat", "wl1_min", get_message::<message::WaterLawHeatWl1Min>(attrs)),
+        ("heat", "wl2_max", get_message::<message::WaterLawHeatWl2Max>(attrs)),
+        ("heat", "wl2_min", get_message::<message::WaterLawHeatWl2Min>(attrs)),
+        ("cool", "outdoor_max", get_message::<message::WaterLawCoolOutdoorMax>(attrs)),
+        ("cool", "outdoor_min", get_message::<message::WaterLawCoolOutdoorMin>(attrs)),
+        ("cool", "wl1_max", get_message::<message::WaterLawCoolWl1Max>(attrs)),
+        ("cool", "wl1_min", get_message::<message::WaterLawCoolWl1Min>(attrs)),
+        ("cool", "wl2_max", get_message::<message::WaterLawCoolWl2Max>(attrs)),
+        ("cool", "wl2_min", get_message::<message::WaterLawCoolWl2Min>(attrs)),
+    ];
+
+    for (mode, point, temp) in points {
+        if let Some(temp) = temp {
+            m.gauge_kv("water_law_curve_celsius", m.celsius(temp),
+                &[("mode", mode), ("point", point)])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// 0/1 gauges for states worth alerting on, so alert rules don't need to
+/// know the raw enum values
+fn render_status(m: &mut AddressMetrics, attrs: &AttrMap) -> fmt::Result {
+    let defrost = get_message::<message::Defrost>(attrs)
+        .or_else(|| get_message::<message::OutdoorDefrostStage>(attrs));
+
+    if let Some(stage) = defrost {
+        m.gauge("defrosting", u8::from(stage.is_active()))?;
+    }
+
+    match get_message::<message::Thermo>(attrs) {
+        Some(UnknownOr::Known(thermo)) => {
+            m.gauge("thermo_on", u8::from(thermo == ThermoState::On))?;
+        }
+        // whether an unknown state means demand or not can't be told, so
+        // export it as is rather than guess
+        Some(UnknownOr::Unknown(raw)) => {
+            m.gauge_kv("thermo_unknown_state", 1, &[("value", &raw.to_string())])?;
+        }
+        None => {}
+    }
+
+    if let Some(code) = get_message::<message::ErrorCode>(attrs) {
+        m.gauge("fault_active", u8::from(code != 0))?;
+    }
+
+    Ok(())
+}
+
 fn get_message<M: IsMessage>(attrs: &AttrMap) -> Option<M::Value> {
     let value = attrs.get(&M::ID)?;
     M::Value::try_from_value(*value)
@@ -179,16 +394,25 @@ fn get_message<M: IsMessage>(attrs: &AttrMap) -> Option<M::Value> {
 
 struct AddressMetrics<'a> {
     out: &'a mut String,
+    bus: &'a str,
     address: Address,
+    /// scale the unit sends temperatures in
+    scale: TempScale,
 }
 
 impl<'a> AddressMetrics<'a> {
+    pub fn celsius(&self, temp: Celsius) -> f32 {
+        self.scale.decode_float(temp.as_float())
+    }
+
     pub fn gauge(&mut self, name: &str, value: impl Display) -> fmt::Result {
         self.gauge_kv(name, value, &[])
     }
 
     pub fn gauge_kv(&mut self, name: &str, value: impl Display, kvs: &[(&str, &str)]) -> fmt::Result {
-        write!(self.out, "samsung_hvac_{name}{{address=\"{address}\"", address = self.address)?;
+        write!(self.out, "samsung_hvac_{name}{{bus=\"{bus}\",address=\"{address}\"",
+            bus = self.bus,
+            address = self.address)?;
         for (k, v) in kvs {
             write!(self.out, ",{k}=\"{v}\"")?;
         }
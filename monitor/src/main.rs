@@ -1,31 +1,141 @@
-use std::io::{self, IsTerminal, Write};
-use std::process::ExitCode;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitCode, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
 
+use samsunghvac_client::pcap::{PcapError, PcapReader, PcapWriter};
 use samsunghvac_client::transport::{self, TransportOpt, TransportReceiver};
-use samsunghvac_protocol::packet::Address;
+use samsunghvac_protocol::frame::FrameReader;
+use samsunghvac_protocol::message::types::TempScale;
+use samsunghvac_protocol::packet::{Address, DataType, MessageId, Packet, ReadPacketError};
+use samsunghvac_protocol::pretty;
 
 use structopt::StructOpt;
 use thiserror::Error;
 
 /// Monitors traffic on Samsung NASA bus.
-/// Reads from stdin by default if path to serial port not specified.
 #[derive(StructOpt)]
 struct Opt {
     #[structopt(short = "i", long = "ignore", help = "ignore traffic to/from an address")]
     ignore: Vec<Address>,
+    #[structopt(short = "t", long = "type", help = "only show packets of a data type, eg. request")]
+    data_type: Vec<DataType>,
+    #[structopt(long = "json", help = "print one JSON object per packet")]
+    json: bool,
+    #[structopt(long = "write-pcap", help = "also write received packets to a pcapng capture")]
+    write_pcap: Option<PathBuf>,
+    #[structopt(long = "read-pcap", help = "read packets from a pcapng capture instead of the bus")]
+    read_pcap: Option<PathBuf>,
+    #[structopt(long = "read-raw", help = "read raw frames from a file instead of the bus, - for stdin")]
+    read_raw: Option<PathBuf>,
+    #[structopt(long = "fahrenheit", help = "show temperatures to/from an address that sends them in fahrenheit in celsius")]
+    fahrenheit: Vec<Address>,
+    #[structopt(long = "trigger", help = "message that runs the --exec command, as ID or ID=VALUE")]
+    trigger: Vec<Trigger>,
+    #[structopt(long = "exec", requires = "trigger", help = "run a shell command for each shown packet matching a trigger, with its JSON on stdin")]
+    exec: Option<String>,
     #[structopt(flatten)]
     transport: TransportOpt,
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), ExitCode> {
-    let opt = Opt::from_args();
-    samsunghvac_common::log::init();
+/// Hook commands queued before further matches are dropped
+const EXEC_QUEUE: usize = 16;
+
+/// A message that runs the hook command when seen, optionally only with a
+/// particular raw value
+struct Trigger {
+    id: MessageId,
+    value: Option<i64>,
+}
+
+#[derive(Error, Debug)]
+#[error("expected trigger in the form ID or ID=VALUE")]
+struct InvalidTrigger;
+
+impl FromStr for Trigger {
+    type Err = InvalidTrigger;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, value) = match s.split_once('=') {
+            Some((id, value)) => (id, Some(value.parse().map_err(|_| InvalidTrigger)?)),
+            None => (s, None),
+        };
+
+        let id = id.parse().map_err(|_| InvalidTrigger)?;
+        Ok(Trigger { id, value })
+    }
+}
 
-    run(opt).await.map_err(|err| {
-        log::error!("{err}");
-        ExitCode::FAILURE
-    })
+impl Trigger {
+    fn matches(&self, packet: &Packet) -> bool {
+        packet.data.iter().any(|message| {
+            let value = match message.id.metadata() {
+                Some(meta) => meta.integer(message.value),
+                None => i64::from(message.value.as_u32()),
+            };
+
+            message.id == self.id && self.value.is_none_or(|expected| expected == value)
+        })
+    }
+}
+
+/// Runs the hook command for triggered packets one at a time on a worker
+/// thread, so a slow command doesn't hold up monitoring. Dropping it waits
+/// for queued commands to finish
+struct Hook {
+    triggers: Vec<Trigger>,
+    queue: Option<SyncSender<String>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Hook {
+    fn start(command: String, triggers: Vec<Trigger>) -> Self {
+        let (queue, rx) = mpsc::sync_channel::<String>(EXEC_QUEUE);
+
+        let worker = thread::spawn(move || {
+            for json in rx {
+                exec(&command, &json);
+            }
+        });
+
+        Hook { triggers, queue: Some(queue), worker: Some(worker) }
+    }
+
+    fn on_packet(&self, packet: &Packet, scale: TempScale) {
+        if !self.triggers.iter().any(|trigger| trigger.matches(packet)) {
+            return;
+        }
+
+        let mut json = String::new();
+        pretty::to_json_in(&mut json, packet, scale).unwrap();
+        json.push('\n');
+
+        let Some(queue) = &self.queue else { return };
+
+        if let Err(TrySendError::Full(_)) = queue.try_send(json) {
+            log::warn!("hook command queue full, dropping packet");
+        }
+    }
+}
+
+impl Drop for Hook {
+    fn drop(&mut self) {
+        // closing the queue ends the worker once it's drained
+        self.queue = None;
+
+        if let Some(worker) = self.worker.take() {
+            let _: Result<_, _> = worker.join();
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let opt = Opt::from_args();
+    samsunghvac_app::run(run(opt))
 }
 
 #[derive(Debug, Error)]
@@ -33,25 +143,134 @@ async fn main() -> Result<(), ExitCode> {
 enum RunError {
     OpenBus(#[from] transport::OpenError),
     Monitor(#[from] io::Error),
+    Pcap(#[from] PcapError),
 }
 
-async fn run(opt: Opt) -> Result<(), RunError> {
+async fn run(mut opt: Opt) -> Result<(), RunError> {
+    let hook = opt.exec.clone().map(|command| Hook::start(command, std::mem::take(&mut opt.trigger)));
+    let hook = hook.as_ref();
+
+    if let Some(path) = &opt.read_pcap {
+        let capture = PcapReader::new(BufReader::new(File::open(path)?));
+
+        for captured in capture {
+            match captured {
+                Ok(captured) => show(&captured.packet, &opt, hook),
+                Err(err @ PcapError::Parse(_)) => log::warn!("{err}"),
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Some(path) = &opt.read_raw {
+        if path.as_os_str() == "-" {
+            read_raw(io::stdin().lock(), &opt, hook)?;
+        } else {
+            read_raw(BufReader::new(File::open(path)?), &opt, hook)?;
+        }
+
+        return Ok(());
+    }
+
+    let mut capture = match &opt.write_pcap {
+        Some(path) => Some(PcapWriter::new(BufWriter::new(File::create(path)?))?),
+        None => None,
+    };
+
     let (mut rd, _wr) = transport::open(&opt.transport).await?;
-    monitor(&mut rd, &opt.ignore).await?;
+    monitor(&mut rd, capture.as_mut(), &opt, hook).await?;
     Ok(())
 }
 
-async fn monitor(rd: &mut TransportReceiver, ignore: &[Address]) -> Result<(), io::Error> {
+async fn monitor(
+    rd: &mut TransportReceiver,
+    mut capture: Option<&mut PcapWriter<BufWriter<File>>>,
+    opt: &Opt,
+    hook: Option<&Hook>,
+) -> Result<(), RunError> {
     loop {
         let packet = rd.read().await?;
 
-        if ignore.contains(&packet.source) || ignore.contains(&packet.destination) {
-            continue;
+        if let Some(capture) = capture.as_mut() {
+            capture.write(SystemTime::now(), &packet)?;
+            capture.flush()?;
         }
 
-        let mut rendered = String::new();
-        samsunghvac_protocol::pretty::pretty_print(&mut rendered, &packet, use_color()).unwrap();
-        std::io::stdout().write_all(rendered.as_bytes()).unwrap();
+        show(&packet, opt, hook);
+    }
+}
+
+fn read_raw(input: impl io::Read, opt: &Opt, hook: Option<&Hook>) -> Result<(), io::Error> {
+    for packet in FrameReader::new(input) {
+        match packet {
+            Ok(packet) => show(&packet, opt, hook),
+            Err(ReadPacketError::Io(err)) => return Err(err),
+            Err(err) => log::warn!("{err}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn show(packet: &Packet, opt: &Opt, hook: Option<&Hook>) {
+    let ignore = &opt.ignore;
+
+    if ignore.contains(&packet.source) || ignore.contains(&packet.destination) {
+        return;
+    }
+
+    if !opt.data_type.is_empty() && !opt.data_type.contains(&packet.data_type) {
+        return;
+    }
+
+    let fahrenheit = &opt.fahrenheit;
+
+    let scale = match fahrenheit.contains(&packet.source) || fahrenheit.contains(&packet.destination) {
+        true => TempScale::Fahrenheit,
+        false => TempScale::Celsius,
+    };
+
+    if let Some(hook) = hook {
+        hook.on_packet(packet, scale);
+    }
+
+    let mut rendered = String::new();
+    if opt.json {
+        pretty::to_json_in(&mut rendered, packet, scale).unwrap();
+        rendered.push('\n');
+    } else {
+        pretty::pretty_print_in(&mut rendered, packet, use_color(), scale).unwrap();
+    }
+    std::io::stdout().write_all(rendered.as_bytes()).unwrap();
+}
+
+/// Runs a hook command to completion, with `stdin` as its input
+fn exec(command: &str, stdin: &str) {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            log::warn!("running {command:?}: {err}");
+            return;
+        }
+    };
+
+    // the command may not read its input, that's fine:
+    if let Some(mut pipe) = child.stdin.take() {
+        let _: Result<_, _> = pipe.write_all(stdin.as_bytes());
+    }
+
+    match child.wait() {
+        Ok(status) if !status.success() => log::warn!("{command:?} exited with {status}"),
+        Ok(_) => {}
+        Err(err) => log::warn!("waiting for {command:?}: {err}"),
     }
 }
 
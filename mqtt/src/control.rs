@@ -1,19 +1,30 @@
 use std::cell::Ref;
 use std::cmp;
+use std::collections::HashMap;
+use std::io;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use samsunghvac_client::message::MessageSet;
-use samsunghvac_client::{Client, Error};
+use samsunghvac_client::{read_messages, Client, ClientOptions, Error};
+use samsunghvac_client::watch::Watch;
 use samsunghvac_client::transport::TransportOpt;
-use samsunghvac_protocol::message::types::{Celsius, FanSetting, OperationMode, PowerSetting};
+use samsunghvac_protocol::message::types::{Celsius, FanSetting, OperationMode, PowerSetting, TempScale, UnknownOr};
 use samsunghvac_protocol::message::{self, IsMessage};
+use samsunghvac_protocol::packet::addresses::DeviceClass;
 use samsunghvac_protocol::packet::{Address, Message};
 use tokio::sync::watch;
 use tokio::task;
 
+use crate::guard::CycleGuard;
+use crate::persist;
 use crate::util::NotifyCell;
 use crate::DeviceConfig;
 
+/// Home Assistant sends each attribute of a climate change as its own
+/// command, this merges them into one packet to the unit
+const REQUEST_BATCH_WINDOW: Duration = Duration::from_millis(20);
+
 #[derive(Clone)]
 pub struct SamsungHvac {
     inner: Rc<Inner>,
@@ -23,11 +34,31 @@ struct Inner {
     client: Client,
     params: Params,
     shared: Rc<Shared>,
+    /// log requests instead of sending them, see [`DeviceConfig::dry_run`]
+    dry_run: bool,
 }
 
 struct Shared {
     address: Address,
     state: NotifyCell<State>,
+    last_seen: NotifyCell<HashMap<Address, Instant>>,
+    guard: Option<CycleGuard>,
+    /// scale the unit puts temperatures on the wire in, state is always
+    /// kept in celsius
+    scale: TempScale,
+}
+
+impl Shared {
+    /// Updates state from messages from the unit, or assumed accepted by it
+    fn update(&self, data: &MessageSet) {
+        let mut state = self.state.borrow_mut();
+        let before = state.power;
+        update_state(&mut state, data, self.scale);
+
+        if let Some(guard) = &self.guard {
+            guard.observe(before, state.power);
+        }
+    }
 }
 
 #[derive(Default, Clone)]
@@ -37,13 +68,49 @@ pub struct State {
     pub fan: Option<FanSetting>,
     pub set_temp: Option<Celsius>,
     pub current_temp: Option<Celsius>,
+    /// Fields restored from disk that have not yet been confirmed by a
+    /// read or notification from the unit
+    pub restored: Restored,
+}
+
+impl State {
+    /// Whether any field is still only restored from disk
+    pub fn assumed(&self) -> bool {
+        let Restored { power, mode, fan, set_temp, current_temp } = self.restored;
+        power || mode || fan || set_temp || current_temp
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct Restored {
+    pub power: bool,
+    pub mode: bool,
+    pub fan: bool,
+    pub set_temp: bool,
+    pub current_temp: bool,
+}
+
+impl Restored {
+    pub const ALL: Restored = Restored {
+        power: true,
+        mode: true,
+        fan: true,
+        set_temp: true,
+        current_temp: true,
+    };
 }
 
 pub struct Params {
     pub cooling_range: TempRange,
     pub heating_range: TempRange,
+    /// resolution of set temperatures accepted by the unit, in degrees of
+    /// its wire scale
+    pub temp_step: f32,
 }
 
+/// Resolution of the protocol itself, used unless configured otherwise
+const DEFAULT_TEMP_STEP: f32 = 0.1;
+
 #[derive(Clone, Copy)]
 pub struct TempRange {
     pub low: Celsius,
@@ -70,6 +137,32 @@ impl TempRange {
     pub fn clamp(&self, temp: Celsius) -> Celsius {
         temp.clamp(self.low, self.high)
     }
+
+    /// Clamps to the range and rounds to the nearest multiple of `step`,
+    /// staying within the range if its bounds aren't multiples themselves.
+    /// A range with no multiple of `step` in it can't be rounded to, so
+    /// the clamped temperature is left as is
+    pub fn constrain(&self, temp: Celsius, step: f32) -> Celsius {
+        let clamped = self.clamp(temp);
+        let temp = clamped.as_float();
+        let rounded = (temp / step).round() * step;
+
+        let rounded = if rounded > self.high.as_float() {
+            rounded - step
+        } else if rounded < self.low.as_float() {
+            rounded + step
+        } else {
+            rounded
+        };
+
+        let rounded = Celsius::from_float(rounded);
+
+        if rounded < self.low || rounded > self.high {
+            return clamped;
+        }
+
+        rounded
+    }
 }
 
 impl SamsungHvac {
@@ -79,25 +172,56 @@ impl SamsungHvac {
         let shared = Rc::new(Shared {
             address: config.address,
             state: NotifyCell::default(),
+            last_seen: NotifyCell::default(),
+            guard: config.cycle_guard.as_ref().map(CycleGuard::new),
+            scale: config.wire_scale,
         });
 
-        let client = Client::connect(&transport, Callbacks {
+        if let Some(path) = &config.state_file {
+            match persist::load(path) {
+                Ok(Some(state)) => { *shared.state.borrow_mut() = state; }
+                Ok(None) => {}
+                Err(err) => { log::warn!("loading state from {}: {err}", path.display()); }
+            }
+        }
+
+        // ride out busd restarts rather than exiting
+        let mut options = ClientOptions::new()
+            .batch_window(REQUEST_BATCH_WINDOW)
+            .reconnect(true);
+        if let Some(address) = config.client_address {
+            options = options.address(address);
+        }
+
+        let client = Client::connect(&transport, options, Callbacks {
             shared: shared.clone()
         }).await?;
 
         // read essential initial params first:
-        let params = read_params(&client, config.address).await?;
+        let temp_step = config.temp_step.unwrap_or(DEFAULT_TEMP_STEP);
+        let params = read_params(&client, config.address, temp_step, config.wire_scale).await?;
+
+        if config.dry_run {
+            log::warn!("dry run: requests to {} will be logged, not sent", config.address);
+        }
 
         let inner = Rc::new(Inner {
             client,
             params,
             shared,
+            dry_run: config.dry_run,
         });
 
         // read initial hvac state asynchronously to constructor:
         task::spawn_local(read_state(inner.clone()));
 
-        Ok(SamsungHvac { inner })
+        let hvac = SamsungHvac { inner };
+
+        if let Some(path) = &config.state_file {
+            task::spawn_local(persist::persist_task(hvac.clone(), path.clone()));
+        }
+
+        Ok(hvac)
     }
 
     pub fn state(&self) -> Ref<'_, State> {
@@ -108,6 +232,30 @@ impl SamsungHvac {
         self.inner.shared.state.subscribe()
     }
 
+    /// Scale the unit puts temperatures on the wire in
+    pub fn wire_scale(&self) -> TempScale {
+        self.inner.shared.scale
+    }
+
+    pub fn address(&self) -> Address {
+        self.inner.shared.address
+    }
+
+    /// Time each unit on the bus was last heard from
+    pub fn last_seen(&self) -> Ref<'_, HashMap<Address, Instant>> {
+        self.inner.shared.last_seen.borrow()
+    }
+
+    pub fn last_seen_updated(&self) -> watch::Receiver<()> {
+        self.inner.shared.last_seen.subscribe()
+    }
+
+    /// Subscribes to an attribute of any unit on the bus, updated as
+    /// it notifies
+    pub fn watch<M: IsMessage>(&self, address: Address) -> Watch<M> {
+        self.inner.client.watch(address)
+    }
+
     pub fn range(&self) -> TempRange {
         match self.state().mode {
             Some(OperationMode::Heat) => self.inner.params.heating_range,
@@ -116,7 +264,55 @@ impl SamsungHvac {
         }
     }
 
+    /// Resolution of set temperatures accepted by the unit, in celsius
+    pub fn temp_step(&self) -> f32 {
+        match self.inner.shared.scale {
+            TempScale::Celsius => self.inner.params.temp_step,
+            TempScale::Fahrenheit => self.inner.params.temp_step * 5.0 / 9.0,
+        }
+    }
+
+    /// Constrains a set temperature to one the unit will accept in its
+    /// current mode
+    pub fn constrain_temp(&self, temp: Celsius) -> Celsius {
+        self.inner.shared.scale.decode(self.wire_temp(temp))
+    }
+
+    /// Converts a set temperature to the unit's wire scale, constrained to
+    /// what it accepts. Rounding to the step happens in the wire scale, as
+    /// a step in one scale falls between steps in the other
+    fn wire_temp(&self, temp: Celsius) -> Celsius {
+        wire_temp(temp, self.range(), self.inner.params.temp_step, self.inner.shared.scale)
+    }
+
     pub async fn request(&self, messages: &[Message]) -> Result<(), Error> {
+        let mut messages = messages.to_vec();
+
+        for msg in &mut messages {
+            if let Some(temp) = msg.get::<message::SetTemp>() {
+                *msg = message::new::<message::SetTemp>(self.wire_temp(temp));
+            }
+        }
+
+        if let Some(guard) = &self.inner.shared.guard {
+            let power = self.state().power;
+            if !guard.admit(power, &mut messages).await {
+                return Ok(());
+            }
+        }
+
+        let messages = messages.as_slice();
+
+        if self.inner.dry_run {
+            log::info!("dry run, would request to {address}: {messages}",
+                address = self.inner.shared.address,
+                messages = MessageSet::new(messages));
+
+            // act as if the unit accepted it, so the new state is published
+            self.inner.shared.update(&MessageSet::new(messages));
+            return Ok(());
+        }
+
         log::debug!("request to {address}: {messages}",
             address = self.inner.shared.address,
             messages = MessageSet::new(messages));
@@ -137,13 +333,25 @@ struct Callbacks {
 
 impl samsunghvac_client::Callbacks for Callbacks {
     fn on_notification(&self, sender: Address, data: &MessageSet) {
+        self.shared.last_seen.borrow_mut().insert(sender, Instant::now());
+
         if sender == self.shared.address {
             log::debug!("notification from {sender}: {data}");
-
-            let mut state = self.shared.state.borrow_mut();
-            update_state(&mut state, data);
+            self.shared.update(data);
         }
     }
+
+    fn on_new_device(&self, address: Address, class: DeviceClass) {
+        log::info!("new device on bus: {address} ({class})");
+    }
+
+    fn on_disconnected(&self, reason: &io::Error) {
+        log::warn!("lost bus connection: {reason}");
+    }
+
+    fn on_connected(&self) {
+        log::info!("bus connection restored");
+    }
 }
 
 async fn read_state(inner: Rc<Inner>) {
@@ -157,8 +365,9 @@ async fn read_state(inner: Rc<Inner>) {
 
     match result {
         Ok(data) => {
-            let mut state = inner.shared.state.borrow_mut();
-            update_state(&mut state, &data);
+            let address = inner.shared.address;
+            inner.shared.last_seen.borrow_mut().insert(address, Instant::now());
+            inner.shared.update(&data);
         }
         Err(err) => {
             log::warn!("reading initial hvac state: {err}");
@@ -166,29 +375,47 @@ async fn read_state(inner: Rc<Inner>) {
     }
 }
 
-fn update_state(state: &mut State, data: &MessageSet) {
+/// Converts a set temperature to `scale`, constrained to `range` and
+/// rounded to `step` in that scale
+fn wire_temp(temp: Celsius, range: TempRange, step: f32, scale: TempScale) -> Celsius {
+    let range = TempRange { low: scale.encode(range.low), high: scale.encode(range.high) };
+    range.constrain(scale.encode(temp), step)
+}
+
+fn update_state(state: &mut State, data: &MessageSet, scale: TempScale) {
     if let Some(power) = data.get::<message::Power>() {
         state.power = Some(power);
+        state.restored.power = false;
     }
 
     if let Some(mode) = data.get::<message::Mode>() {
         state.mode = Some(mode);
+        state.restored.mode = false;
     }
 
     if let Some(fan) = data.get::<message::FanMode>() {
-        state.fan = Some(fan);
+        // a speed the climate entity has no mode for is shown as unset,
+        // rather than as whichever mode was last known
+        if let UnknownOr::Unknown(raw) = fan {
+            log::warn!("unit reported unknown fan setting: {raw}");
+        }
+
+        state.fan = fan.known();
+        state.restored.fan = false;
     }
 
     if let Some(temp) = data.get::<message::SetTemp>() {
+        state.restored.set_temp = false;
         if has_temperature(state) {
-            state.set_temp = Some(temp);
+            state.set_temp = Some(scale.decode(temp));
         } else {
             state.set_temp = None;
         }
     }
 
     if let Some(temp) = data.get::<message::CurrentTemp>() {
-        state.current_temp = Some(temp);
+        state.current_temp = Some(scale.decode(temp));
+        state.restored.current_temp = false;
     }
 }
 
@@ -199,31 +426,123 @@ fn has_temperature(state: &State) -> bool {
         return false;
     }
 
-    match state.mode {
-        None => false,
-        Some(OperationMode::Fan) => false,
-        _ => true,
-    }
+    !matches!(state.mode, None | Some(OperationMode::Fan))
 }
 
-async fn read_params(client: &Client, address: Address) -> Result<Params, Error> {
+async fn read_params(client: &Client, address: Address, temp_step: f32, scale: TempScale)
+    -> Result<Params, Error>
+{
     log::info!("reading initial params from {}", address);
 
-    let reply = client.read(address, &[
-        message::CoolLowTempLimit::ID,
-        message::HeatLowTempLimit::ID,
-        message::CoolHighTempLimit::ID,
-        message::HeatHighTempLimit::ID,
-    ]).await?;
+    let limits = read_messages!(client, address, {
+        cool_low: message::CoolLowTempLimit,
+        heat_low: message::HeatLowTempLimit,
+        cool_high: message::CoolHighTempLimit,
+        heat_high: message::HeatHighTempLimit,
+    }).await?;
 
     Ok(Params {
         cooling_range: TempRange {
-            low: reply.try_get::<message::CoolLowTempLimit>()?.into(),
-            high: reply.try_get::<message::CoolHighTempLimit>()?.into(),
+            low: scale.decode(limits.cool_low.into()),
+            high: scale.decode(limits.cool_high.into()),
         },
         heating_range: TempRange {
-            low: reply.try_get::<message::HeatLowTempLimit>()?.into(),
-            high: reply.try_get::<message::HeatHighTempLimit>()?.into(),
+            low: scale.decode(limits.heat_low.into()),
+            high: scale.decode(limits.heat_high.into()),
         },
+        temp_step,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use samsunghvac_protocol::message::types::FanSetting;
+
+    use super::*;
+
+    fn range(low: f32, high: f32) -> TempRange {
+        TempRange { low: Celsius::from_float(low), high: Celsius::from_float(high) }
+    }
+
+    fn wire(temp: f32, range: TempRange, step: f32, scale: TempScale) -> f32 {
+        wire_temp(Celsius::from_float(temp), range, step, scale).as_float()
+    }
+
+    fn constrain(temp: f32, range: TempRange, step: f32) -> f32 {
+        range.constrain(Celsius::from_float(temp), step).as_float()
+    }
+
+    fn restored_state() -> State {
+        State {
+            power: Some(PowerSetting::On),
+            mode: Some(OperationMode::Cool),
+            fan: Some(FanSetting::Auto),
+            set_temp: Some(Celsius::from_float(22.0)),
+            current_temp: Some(Celsius::from_float(24.0)),
+            restored: Restored::ALL,
+        }
+    }
+
+    #[test]
+    fn partial_update_stays_assumed() {
+        let mut state = restored_state();
+
+        let data = MessageSet::from_vec(vec![message::new::<message::CurrentTemp>(Celsius::from_float(23.0))]);
+        update_state(&mut state, &data, TempScale::Celsius);
+
+        assert!(!state.restored.current_temp);
+        assert!(state.restored.power);
+        assert!(state.assumed());
+    }
+
+    #[test]
+    fn full_update_clears_assumed() {
+        let mut state = restored_state();
+
+        let data = MessageSet::from_vec(vec![
+            message::new::<message::Power>(PowerSetting::On),
+            message::new::<message::Mode>(OperationMode::Heat),
+            message::new::<message::FanMode>(FanSetting::Low.into()),
+            message::new::<message::SetTemp>(Celsius::from_float(21.0)),
+            message::new::<message::CurrentTemp>(Celsius::from_float(23.0)),
+        ]);
+        update_state(&mut state, &data, TempScale::Celsius);
+
+        assert!(!state.assumed());
+    }
+
+    #[test]
+    fn constrain_rounds_to_step() {
+        assert_eq!(constrain(22.3, range(16.0, 30.0), 0.5), 22.5);
+        assert_eq!(constrain(22.2, range(16.0, 30.0), 0.5), 22.0);
+        assert_eq!(constrain(35.0, range(16.0, 30.0), 0.5), 30.0);
+    }
+
+    #[test]
+    fn constrain_stays_inside_bounds_off_step() {
+        assert_eq!(constrain(40.0, range(16.2, 29.8), 1.0), 29.0);
+        assert_eq!(constrain(0.0, range(16.2, 29.8), 1.0), 17.0);
+    }
+
+    #[test]
+    fn constrain_range_narrower_than_step() {
+        assert_eq!(constrain(20.0, range(20.2, 20.4), 1.0), 20.2);
+        assert_eq!(constrain(21.0, range(20.6, 20.8), 1.0), 20.8);
+        assert_eq!(constrain(20.3, range(20.3, 20.3), 0.5), 20.3);
+    }
+
+    #[test]
+    fn wire_temp_rounds_in_wire_scale() {
+        // 22 °C is 71.6 °F, rounded to a whole degree fahrenheit
+        assert_eq!(wire(22.0, range(16.0, 30.0), 1.0, TempScale::Fahrenheit), 72.0);
+        assert_eq!(wire(22.3, range(16.0, 30.0), 0.5, TempScale::Celsius), 22.5);
+    }
+
+    #[test]
+    fn wire_temp_clamps_in_wire_scale() {
+        // 30 °C is exactly 86 °F
+        assert_eq!(wire(35.0, range(16.0, 30.0), 1.0, TempScale::Fahrenheit), 86.0);
+        // 16 °C is 60.8 °F, so the nearest whole degree inside is 61
+        assert_eq!(wire(10.0, range(16.0, 30.0), 1.0, TempScale::Fahrenheit), 61.0);
+    }
+}
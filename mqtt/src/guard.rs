@@ -0,0 +1,222 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+use samsunghvac_protocol::message::{self, types::PowerSetting};
+use samsunghvac_protocol::packet::Message;
+use tokio::time::Instant;
+
+use crate::CycleGuardConfig;
+
+/// Enforces a minimum off time and a minimum time between power changes,
+/// so automations can't short-cycle the compressor
+pub struct CycleGuard {
+    min_off: Option<Duration>,
+    min_toggle: Option<Duration>,
+    defer: bool,
+    last_toggle: Cell<Option<Instant>>,
+    last_off: Cell<Option<Instant>>,
+    /// bumped by every power command, so a held one can tell it has been
+    /// replaced by a later one
+    generation: Cell<u64>,
+}
+
+impl CycleGuard {
+    pub fn new(config: &CycleGuardConfig) -> Self {
+        CycleGuard {
+            min_off: config.min_off_secs.map(Duration::from_secs),
+            min_toggle: config.min_toggle_secs.map(Duration::from_secs),
+            defer: config.defer,
+            last_toggle: Cell::new(None),
+            last_off: Cell::new(None),
+            generation: Cell::new(0),
+        }
+    }
+
+    /// Records a change in the unit's power setting, whoever made it.
+    /// Changes between on settings don't count
+    pub fn observe(&self, before: Option<PowerSetting>, after: Option<PowerSetting>) {
+        let (Some(before), Some(power)) = (before, after) else { return };
+
+        if is_on(before) == is_on(power) {
+            return;
+        }
+
+        let now = Instant::now();
+        self.last_toggle.set(Some(now));
+
+        if power == PowerSetting::Off {
+            self.last_off.set(Some(now));
+        }
+    }
+
+    /// Checks a request against the guard, given the unit's current power
+    /// setting. A power change that comes too soon is either removed from
+    /// `messages` or, with `defer`, waited out. Returns whether anything
+    /// is left to send
+    pub async fn admit(&self, current: Option<PowerSetting>, messages: &mut Vec<Message>) -> bool {
+        let Some(power) = messages.iter().find_map(|msg| msg.get::<message::Power>()) else {
+            return true;
+        };
+
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+
+        if current.is_none_or(|current| is_on(current) == is_on(power)) {
+            return true;
+        }
+
+        let Some(wait) = self.wait_for(power) else {
+            return true;
+        };
+
+        if !self.defer {
+            log::warn!("cycle guard: dropping power {power}, allowed in {wait:?}");
+            messages.retain(|msg| !msg.is::<message::Power>());
+            return !messages.is_empty();
+        }
+
+        log::info!("cycle guard: holding power {power} for {wait:?}");
+        tokio::time::sleep(wait).await;
+
+        if self.generation.get() != generation {
+            log::info!("cycle guard: held power {power} replaced by a later command");
+            messages.retain(|msg| !msg.is::<message::Power>());
+            return !messages.is_empty();
+        }
+
+        true
+    }
+
+    /// How long until the unit may be switched to `power`, if it may not
+    /// be yet
+    fn wait_for(&self, power: PowerSetting) -> Option<Duration> {
+        let after_toggle = self.min_toggle.zip(self.last_toggle.get())
+            .map(|(min, last)| last + min);
+
+        let after_off = self.min_off.zip(self.last_off.get())
+            .filter(|_| is_on(power))
+            .map(|(min, last)| last + min);
+
+        let allowed = after_toggle.max(after_off)?;
+        allowed.checked_duration_since(Instant::now())
+            .filter(|wait| !wait.is_zero())
+    }
+}
+
+fn is_on(power: PowerSetting) -> bool {
+    power != PowerSetting::Off
+}
+
+#[cfg(test)]
+mod tests {
+    use samsunghvac_protocol::message::types::Celsius;
+
+    use super::*;
+
+    fn guard(min_off: Option<u64>, min_toggle: Option<u64>, defer: bool) -> CycleGuard {
+        CycleGuard::new(&CycleGuardConfig { min_off_secs: min_off, min_toggle_secs: min_toggle, defer })
+    }
+
+    fn command(power: PowerSetting) -> Vec<Message> {
+        vec![
+            message::new::<message::Power>(power),
+            message::new::<message::SetTemp>(Celsius::from_float(22.0)),
+        ]
+    }
+
+    fn has_power(messages: &[Message]) -> bool {
+        messages.iter().any(|msg| msg.is::<message::Power>())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passes_commands_without_power() {
+        let guard = guard(Some(300), None, false);
+        guard.observe(Some(PowerSetting::On), Some(PowerSetting::Off));
+
+        let mut messages = vec![message::new::<message::SetTemp>(Celsius::from_float(22.0))];
+        assert!(guard.admit(Some(PowerSetting::Off), &mut messages).await);
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drops_only_power_too_soon_after_off() {
+        let guard = guard(Some(300), None, false);
+        guard.observe(Some(PowerSetting::On), Some(PowerSetting::Off));
+
+        let mut messages = command(PowerSetting::On);
+        assert!(guard.admit(Some(PowerSetting::Off), &mut messages).await);
+        assert!(!has_power(&messages));
+        assert_eq!(messages.len(), 1);
+
+        let mut messages = vec![message::new::<message::Power>(PowerSetting::On)];
+        assert!(!guard.admit(Some(PowerSetting::Off), &mut messages).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn admits_power_once_min_off_has_passed() {
+        let guard = guard(Some(300), None, false);
+        guard.observe(Some(PowerSetting::On), Some(PowerSetting::Off));
+
+        tokio::time::advance(Duration::from_secs(300)).await;
+
+        let mut messages = command(PowerSetting::On);
+        assert!(guard.admit(Some(PowerSetting::Off), &mut messages).await);
+        assert!(has_power(&messages));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn min_off_does_not_hold_turning_off() {
+        let guard = guard(Some(300), None, false);
+        guard.observe(Some(PowerSetting::Off), Some(PowerSetting::On));
+
+        let mut messages = command(PowerSetting::Off);
+        assert!(guard.admit(Some(PowerSetting::On), &mut messages).await);
+        assert!(has_power(&messages));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn changes_between_on_settings_are_not_toggles() {
+        let guard = guard(None, Some(300), false);
+        guard.observe(Some(PowerSetting::On), Some(PowerSetting::On2));
+
+        let mut messages = command(PowerSetting::Off);
+        assert!(guard.admit(Some(PowerSetting::On2), &mut messages).await);
+        assert!(has_power(&messages));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn defer_holds_power_until_allowed() {
+        let guard = guard(None, Some(300), true);
+        guard.observe(Some(PowerSetting::Off), Some(PowerSetting::On));
+
+        let start = Instant::now();
+        let mut messages = command(PowerSetting::Off);
+        assert!(guard.admit(Some(PowerSetting::On), &mut messages).await);
+        assert!(has_power(&messages));
+        assert!(start.elapsed() >= Duration::from_secs(300));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn superseded_deferred_command_keeps_other_messages() {
+        let guard = guard(None, Some(300), true);
+        guard.observe(Some(PowerSetting::Off), Some(PowerSetting::On));
+
+        let mut held = command(PowerSetting::Off);
+        let mut later = command(PowerSetting::Off);
+
+        let (held_admitted, later_admitted) = tokio::join!(
+            guard.admit(Some(PowerSetting::On), &mut held),
+            async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                guard.admit(Some(PowerSetting::On), &mut later).await
+            },
+        );
+
+        assert!(held_admitted);
+        assert!(!has_power(&held));
+        assert_eq!(held.len(), 1);
+
+        assert!(later_admitted);
+        assert!(has_power(&later));
+    }
+}
@@ -4,14 +4,17 @@ use std::process::ExitCode;
 
 use futures::future;
 use samsunghvac_client::transport;
+use samsunghvac_protocol::message::types::TempScale;
 use samsunghvac_protocol::packet::Address;
 use serde::{Deserialize, Deserializer};
 use structopt::StructOpt;
 use thiserror::Error;
-use tokio::task::LocalSet;
 
 mod control;
+mod guard;
 mod mqtt;
+mod persist;
+mod tls;
 mod types;
 mod util;
 
@@ -20,18 +23,9 @@ struct Opt {
 
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), ExitCode> {
+fn main() -> ExitCode {
     let opt = Opt::from_args();
-    samsunghvac_common::log::init();
-
-    let local = LocalSet::new();
-    let result = local.run_until(run(opt)).await;
-
-    result.map_err(|err| {
-        log::error!("{err}");
-        ExitCode::FAILURE
-    })
+    samsunghvac_app::run(run(opt))
 }
 
 #[derive(Error, Debug)]
@@ -42,12 +36,14 @@ enum RunError {
     OpenBus(#[from] transport::OpenError),
     #[error(transparent)]
     Client(#[from] samsunghvac_client::Error),
+    #[error("mqtt tls config: {0}")]
+    Tls(#[from] tls::TlsConfigError),
 }
 
 async fn run(_: Opt) -> Result<(), RunError> {
     let config = load_config()?;
     let hvac = control::SamsungHvac::new(&config.device).await?;
-    mqtt::start(&config.mqtt, &config.discovery, hvac).await;
+    mqtt::start(&config.mqtt, &config.discovery, config.outdoor.as_ref(), hvac).await?;
     // we're started, now run forever:
     future::pending().await
 }
@@ -80,7 +76,7 @@ fn config_path() -> PathBuf {
         return cwd_config;
     }
 
-    return PathBuf::from("/etc/samsunghvac/mqtt.toml");
+    PathBuf::from("/etc/samsunghvac/mqtt.toml")
 }
 
 #[derive(Deserialize)]
@@ -88,15 +84,27 @@ struct Config {
     mqtt: MqttConfig,
     discovery: DiscoveryConfig,
     device: DeviceConfig,
+    outdoor: Option<OutdoorConfig>,
 }
 
 #[derive(Deserialize, Clone)]
 struct MqttConfig {
+    /// broker hostname, or a ws:// or wss:// url to connect over websockets
     host: String,
     port: Option<u16>,
     #[serde(flatten)]
     credentials: Option<MqttCredentials>,
     client_id: String,
+    tls: Option<MqttTlsConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+struct MqttTlsConfig {
+    ca_file: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    #[serde(default)]
+    insecure: bool,
 }
 
 #[derive(Deserialize, Clone)]
@@ -117,6 +125,61 @@ struct DeviceConfig {
     bus: PathBuf,
     #[serde(deserialize_with = "deserialize_address")]
     address: Address,
+    /// persists last known state here, so it can be published on startup
+    state_file: Option<PathBuf>,
+    /// resolution of set temperatures the unit accepts, eg. 0.5, in its
+    /// wire scale. commands are rounded to this before sending
+    temp_step: Option<f32>,
+    /// source address for packets we send, must be unique on the bus
+    #[serde(default, deserialize_with = "deserialize_opt_address")]
+    client_address: Option<Address>,
+    /// logs requests that would be sent to the unit, and updates state
+    /// as if they succeeded, without writing to the bus
+    #[serde(default)]
+    dry_run: bool,
+    /// limits how often the unit may be switched on and off
+    cycle_guard: Option<CycleGuardConfig>,
+    /// scale the unit sends and expects temperatures in, "celsius" or
+    /// "fahrenheit". temperatures are converted to and from celsius
+    #[serde(default, deserialize_with = "deserialize_temp_scale")]
+    wire_scale: TempScale,
+}
+
+#[derive(Deserialize, Clone)]
+struct CycleGuardConfig {
+    /// seconds the unit must stay off before it may be turned back on
+    min_off_secs: Option<u64>,
+    /// seconds between any two power changes, on or off
+    min_toggle_secs: Option<u64>,
+    /// hold power changes that come too soon until they're allowed,
+    /// rather than dropping them. a later power command replaces a held one
+    #[serde(default)]
+    defer: bool,
+}
+
+#[derive(Deserialize, Clone)]
+struct OutdoorConfig {
+    /// outdoor unit to publish telemetry from, as its own device
+    #[serde(deserialize_with = "deserialize_address")]
+    address: Address,
+    /// scale the outdoor unit sends temperatures in, if not the same as
+    /// the device's wire_scale
+    #[serde(default, deserialize_with = "deserialize_opt_temp_scale")]
+    wire_scale: Option<TempScale>,
+}
+
+fn deserialize_opt_address<'de, D>(de: D) -> Result<Option<Address>, D::Error> where D: Deserializer<'de> {
+    deserialize_address(de).map(Some)
+}
+
+fn deserialize_opt_temp_scale<'de, D>(de: D) -> Result<Option<TempScale>, D::Error> where D: Deserializer<'de> {
+    deserialize_temp_scale(de).map(Some)
+}
+
+fn deserialize_temp_scale<'de, D>(de: D) -> Result<TempScale, D::Error> where D: Deserializer<'de> {
+    let scale = Cow::<str>::deserialize(de)?;
+    let scale = scale.parse().map_err(serde::de::Error::custom)?;
+    Ok(scale)
 }
 
 fn deserialize_address<'de, D>(de: D) -> Result<Address, D::Error> where D: Deserializer<'de> {
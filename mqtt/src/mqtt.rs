@@ -1,20 +1,24 @@
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::str::{self, FromStr};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use rumqttc::{AsyncClient, ConnectionError, EventLoop, MqttOptions, QoS};
+use rumqttc::{AsyncClient, ConnectionError, EventLoop, MqttOptions, QoS, Transport};
 use serde::Serialize;
 use tokio::sync::watch;
 use tokio::{task, time};
 
 use samsunghvac_client::Error;
-use samsunghvac_protocol::message::types::{Celsius, OperationMode, PowerSetting};
+use samsunghvac_protocol::message::types::{Celsius, FanSetting, OperationMode, PowerSetting};
 use samsunghvac_protocol::message;
+use samsunghvac_protocol::packet::Address;
 
 use crate::control::{self, SamsungHvac};
+use crate::tls::{self, TlsConfigError};
 use crate::types::{FanMode, HvacMode};
-use crate::{DiscoveryConfig, MqttConfig};
+use crate::{DiscoveryConfig, MqttConfig, OutdoorConfig};
+
+mod outdoor;
 
 const REFUSED_BACKOFF: Duration = Duration::from_secs(1);
 const LIVENESS_TIMEOUT: Duration = Duration::from_secs(60);
@@ -24,15 +28,17 @@ struct MqttCtx {
     hvac: SamsungHvac,
     discovery: DiscoveryConfig,
     topics: Topics,
+    outdoor: Option<outdoor::Outdoor>,
     announce: watch::Sender<()>,
 }
 
 pub async fn start(
     mqtt: &MqttConfig,
     discovery: &DiscoveryConfig,
+    outdoor: Option<&OutdoorConfig>,
     hvac: SamsungHvac,
-) {
-    let options = mqtt_options(mqtt);
+) -> Result<(), TlsConfigError> {
+    let options = mqtt_options(mqtt)?;
     let (mqtt, eventloop) = AsyncClient::new(options, 8);
 
     let (announce, announce_rx) = watch::channel(());
@@ -42,6 +48,7 @@ pub async fn start(
         hvac: hvac.clone(),
         discovery: discovery.clone(),
         topics: Topics::new(discovery),
+        outdoor: outdoor.map(|config| outdoor::Outdoor::new(config, discovery, hvac.wire_scale())),
         announce,
     });
 
@@ -52,19 +59,27 @@ pub async fn start(
     subscribe_topics(&ctx).await;
 
     // state updates
-    let (liveness, liveness_rx) = watch::channel(());
-    task::spawn_local(availability_task(ctx.clone(), liveness_rx));
-    task::spawn_local(update_state_task(ctx.clone(), liveness.clone()));
+    task::spawn_local(availability_task(ctx.clone()));
+    task::spawn_local(update_state_task(ctx.clone()));
+
+    if ctx.outdoor.is_some() {
+        outdoor::start(&ctx);
+    }
 
     // spawn task responsible for device announcements
     task::spawn_local(announce_task(ctx.clone(), announce_rx));
+
+    Ok(())
 }
 
-async fn update_state_task(ctx: Rc<MqttCtx>, liveness: watch::Sender<()>) {
+async fn update_state_task(ctx: Rc<MqttCtx>) {
     let topics = &ctx.topics.climate;
     let mut updated = ctx.hvac.state_updated();
     let mut previous_mode = None;
 
+    // state may already have been restored from disk, publish it straight away
+    updated.mark_changed();
+
     while updated.changed().await.is_ok() {
         let state = ctx.hvac.state().clone();
 
@@ -96,22 +111,58 @@ async fn update_state_task(ctx: Rc<MqttCtx>, liveness: watch::Sender<()>) {
             publish(&ctx, &topics.current_temperature, temp).await;
         }
 
-        // notify the availability task of liveness
-        liveness.send_replace(());
+        let attributes = StateAttributes { assumed: state.assumed() };
+        let payload = serde_json::to_string(&attributes).unwrap();
+        publish(&ctx, &topics.attributes, payload).await;
     }
 }
 
-async fn availability_task(ctx: Rc<MqttCtx>, mut liveness: watch::Receiver<()>) {
+/// Publishes availability for each unit seen on the bus. Announced units
+/// start out offline until they're seen, availability changes are
+/// published as soon as a unit is seen, and all units are republished
+/// each time the liveness timeout elapses.
+async fn availability_task(ctx: Rc<MqttCtx>) {
+    let mut seen = ctx.hvac.last_seen_updated();
+    let mut published = HashMap::<Address, bool>::new();
+
+    let announced = [Some(ctx.hvac.address()), ctx.outdoor.as_ref().map(|outdoor| outdoor.address)];
+
+    // publish every unit on the first pass, so announced units that are
+    // never seen still show as offline
+    let mut republish = true;
+
     loop {
-        let result = time::timeout(LIVENESS_TIMEOUT, liveness.changed()).await;
+        let now = Instant::now();
+
+        let mut units = announced.iter().flatten()
+            .map(|address| (*address, false))
+            .collect::<HashMap<_, _>>();
+
+        for (address, last_seen) in ctx.hvac.last_seen().iter() {
+            let online = now.duration_since(*last_seen) < LIVENESS_TIMEOUT;
+            units.insert(*address, online);
+        }
+
+        for (address, online) in units {
+            let changed = published.insert(address, online) != Some(online);
+            if !republish && !changed {
+                continue;
+            }
+
+            let availability = if online { "online" } else { "offline" };
+
+            publish(&ctx, &ctx.topics.unit_availability(address), availability).await;
+
+            if address == ctx.hvac.address() {
+                publish(&ctx, &ctx.topics.climate.availability, availability).await;
+            }
+        }
 
-        let availability = match result {
-            Ok(Ok(())) => "online",
-            Err(_) => "offline",
+        republish = match time::timeout(LIVENESS_TIMEOUT, seen.changed()).await {
+            Ok(Ok(())) => false,
+            Err(_) => true,
             Ok(Err(_)) => { break }
         };
-
-        publish(&ctx, &ctx.topics.climate.availability, availability).await;
     }
 }
 
@@ -120,6 +171,12 @@ async fn announce_task(ctx: Rc<MqttCtx>, mut announce: watch::Receiver<()>) {
         let device = device_config(&ctx);
         let payload = serde_json::to_string(&device).unwrap();
         publish(&ctx, &ctx.topics.device_config, payload).await;
+
+        if let Some(outdoor) = &ctx.outdoor {
+            let device = outdoor.device_config(&ctx);
+            let payload = serde_json::to_string(&device).unwrap();
+            publish(&ctx, &outdoor.topics.device_config, payload).await;
+        }
     }
 }
 
@@ -178,19 +235,16 @@ fn hvac_mode(state: &control::State) -> Option<HvacMode> {
 async fn on_event(ctx: Rc<MqttCtx>, event: rumqttc::Event) {
     use rumqttc::{Packet, Event};
 
-    match event {
-        Event::Incoming(Packet::Publish(packet)) => {
-            let topic = packet.topic;
-            if let Some(payload) = str::from_utf8(&packet.payload).ok() {
-                log::debug!("received: {topic}: {payload}");
-                if let Err(err) = on_message(&ctx, &topic, payload).await {
-                    log::warn!("error dispatching command on {topic}: {err}");
-                }
-            } else {
-                log::warn!("received invalid utf-8: {topic}");
+    if let Event::Incoming(Packet::Publish(packet)) = event {
+        let topic = packet.topic;
+        if let Ok(payload) = str::from_utf8(&packet.payload) {
+            log::debug!("received: {topic}: {payload}");
+            if let Err(err) = on_message(&ctx, &topic, payload).await {
+                log::warn!("error dispatching command on {topic}: {err}");
             }
+        } else {
+            log::warn!("received invalid utf-8: {topic}");
         }
-        _ => {}
     }
 }
 
@@ -236,35 +290,54 @@ async fn on_message(ctx: &MqttCtx, topic: &str, message: &str) -> Result<(), Err
     if ctx.topics.climate.temperature_command == topic {
         let temp = f32::from_str(message).ok().map(Celsius::from_float);
 
+        // the unit may reject temperatures off its step, so round here.
+        // state is re-read after the request, which reflects the rounded
+        // value back to the slider
         if let Some(temp) = temp {
-            let temp = ctx.hvac.range().clamp(temp);
+            let temp = ctx.hvac.constrain_temp(temp);
             messages.push(message::new::<message::SetTemp>(temp));
         }
     }
 
     if ctx.topics.climate.fan_mode_command == topic {
-        let mode = FanMode::from_str(message).ok().map(Into::into);
+        let mode = FanMode::from_str(message).ok().map(FanSetting::from);
 
         if let Some(mode) = mode {
-            messages.push(message::new::<message::FanMode>(mode));
+            messages.push(message::new::<message::FanMode>(mode.into()));
         }
     }
 
     ctx.hvac.request(&messages).await
 }
 
-fn mqtt_options(mqtt: &MqttConfig) -> MqttOptions {
-    let mut options = MqttOptions::new(&mqtt.client_id, &mqtt.host, mqtt.port.unwrap_or(1883));
+fn mqtt_options(mqtt: &MqttConfig) -> Result<MqttOptions, TlsConfigError> {
+    let tls = mqtt.tls.as_ref().map(tls::configuration).transpose()?;
+
+    // websocket urls carry their own port, which rumqttc reads from the
+    // host string directly:
+    let (transport, default_port) = if mqtt.host.starts_with("wss://") {
+        (Transport::Wss(tls.unwrap_or_default()), 443)
+    } else if mqtt.host.starts_with("ws://") {
+        (Transport::Ws, 80)
+    } else if let Some(tls) = tls {
+        (Transport::Tls(tls), 8883)
+    } else {
+        (Transport::Tcp, 1883)
+    };
+
+    let port = mqtt.port.unwrap_or(default_port);
+    let mut options = MqttOptions::new(&mqtt.client_id, &mqtt.host, port);
     options.set_keep_alive(Duration::from_secs(5));
+    options.set_transport(transport);
 
     if let Some(creds) = &mqtt.credentials {
         options.set_credentials(&creds.username, &creds.password);
     }
 
-    options
+    Ok(options)
 }
 
-fn device_config(ctx: &MqttCtx) -> DeviceConfig {
+fn device_config(ctx: &MqttCtx) -> DeviceConfig<'_, ClimateComponent<'_>> {
     let range = ctx.hvac.range();
 
     let component = ClimateComponent {
@@ -276,12 +349,12 @@ fn device_config(ctx: &MqttCtx) -> DeviceConfig {
         min_temp: range.low.as_float(),
         max_temp: range.high.as_float(),
         precision: 0.1,
-        temp_step: 0.1,
+        temp_step: ctx.hvac.temp_step(),
         // swing_modes: EmptyList,
         temperature_unit: 'C',
     };
 
-    let device = DeviceConfig {
+    DeviceConfig {
         device: DeviceMapping {
             name: "Samsung HVAC",
             ids: &ctx.discovery.unique_id,
@@ -293,15 +366,14 @@ fn device_config(ctx: &MqttCtx) -> DeviceConfig {
             (ctx.discovery.object_id.as_str(), component),
         ]),
         qos: 1,
-    };
-
-    device
+    }
 }
 
 struct Topics {
     homeassistant_status: String,
     climate: ClimateComponentTopics,
     device_config: String,
+    units: String,
 }
 
 impl Topics {
@@ -315,17 +387,22 @@ impl Topics {
         Topics {
             homeassistant_status: format!("{prefix}/status"),
             device_config: format!("{prefix}/device/{object_id}/config"),
+            units: format!("{component}/unit"),
             climate,
         }
     }
+
+    pub fn unit_availability(&self, address: Address) -> String {
+        format!("{}/{address}/availability", self.units)
+    }
 }
 
 #[derive(Serialize)]
 struct ClimateComponentTopics {
     // #[serde(rename = "action_topic")]
     // action: String,
-    // #[serde(rename = "json_attributes_topic")]
-    // attributes: String,
+    #[serde(rename = "json_attributes_topic")]
+    attributes: String,
     #[serde(rename = "availability_topic")]
     availability: String,
     #[serde(rename = "current_temperature_topic")]
@@ -350,7 +427,7 @@ impl ClimateComponentTopics {
     pub fn new(base: &str) -> Self {
         ClimateComponentTopics {
             // action: format!("{base}/action"),
-            // attributes: format!("{base}/attributes"),
+            attributes: format!("{base}/attributes"),
             availability: format!("{base}/availability"),
             current_temperature: format!("{base}/current_temperature"),
             fan_mode_command: format!("{base}/fan/set"),
@@ -364,6 +441,13 @@ impl ClimateComponentTopics {
     }
 }
 
+/// Extra attributes published alongside the climate entity state
+#[derive(Serialize)]
+struct StateAttributes {
+    /// state was restored from disk and is not yet confirmed by the unit
+    assumed: bool,
+}
+
 #[derive(Serialize)]
 struct ClimateComponent<'a> {
     #[serde(rename="p")]
@@ -382,12 +466,12 @@ struct ClimateComponent<'a> {
 }
 
 #[derive(Serialize)]
-struct DeviceConfig<'a> {
+struct DeviceConfig<'a, C> {
     device: DeviceMapping<'a>,
     #[serde(rename = "o")]
     origin: OriginMapping<'a>,
     #[serde(rename = "cmps")]
-    components: HashMap<&'a str, ClimateComponent<'a>>,
+    components: HashMap<&'a str, C>,
     qos: usize,
 }
 
@@ -0,0 +1,178 @@
+//! Outdoor unit telemetry, announced to Home Assistant as a device of its
+//! own with read-only diagnostic sensors
+
+use std::fmt::Display;
+use std::rc::Rc;
+
+use serde::Serialize;
+use tokio::task;
+
+use samsunghvac_protocol::message::{self, IsMessage};
+use samsunghvac_protocol::message::types::{Celsius, TempScale};
+use samsunghvac_protocol::packet::Address;
+
+use super::{publish, DeviceConfig, DeviceMapping, MqttCtx, OriginMapping};
+use crate::{DiscoveryConfig, OutdoorConfig};
+
+pub struct Outdoor {
+    pub address: Address,
+    /// scale the outdoor unit sends temperatures in, they're published
+    /// in celsius
+    scale: TempScale,
+    name: String,
+    unique_id: String,
+    pub topics: OutdoorTopics,
+}
+
+pub struct OutdoorTopics {
+    pub device_config: String,
+    sensors: String,
+}
+
+impl OutdoorTopics {
+    fn state(&self, sensor: &Sensor) -> String {
+        format!("{}/{}/state", self.sensors, sensor.key)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sensor {
+    platform: &'static str,
+    key: &'static str,
+    name: &'static str,
+    device_class: Option<&'static str>,
+    unit: Option<&'static str>,
+}
+
+impl Sensor {
+    const fn temperature(key: &'static str, name: &'static str) -> Self {
+        Sensor { platform: "sensor", key, name, device_class: Some("temperature"), unit: Some("°C") }
+    }
+}
+
+const OUTDOOR_TEMP: Sensor = Sensor::temperature("outdoor_temp", "Outdoor temperature");
+const DISCHARGE_TEMP: Sensor = Sensor::temperature("discharge_temp", "Discharge temperature");
+const EXCHANGER_TEMP: Sensor = Sensor::temperature("exchanger_temp", "Heat exchanger temperature");
+
+const DEFROST: Sensor = Sensor {
+    platform: "sensor",
+    key: "defrost",
+    name: "Defrost",
+    device_class: None,
+    unit: None,
+};
+
+const COMPRESSOR: Sensor = Sensor {
+    platform: "binary_sensor",
+    key: "compressor",
+    name: "Compressor",
+    device_class: Some("running"),
+    unit: None,
+};
+
+const SENSORS: [Sensor; 5] = [
+    OUTDOOR_TEMP,
+    DISCHARGE_TEMP,
+    EXCHANGER_TEMP,
+    DEFROST,
+    COMPRESSOR,
+];
+
+impl Outdoor {
+    pub fn new(config: &OutdoorConfig, discovery: &DiscoveryConfig, device_scale: TempScale) -> Self {
+        let prefix = &discovery.prefix;
+        let object_id = format!("{}_outdoor", discovery.object_id);
+
+        Outdoor {
+            address: config.address,
+            scale: config.wire_scale.unwrap_or(device_scale),
+            name: "Samsung HVAC outdoor unit".to_owned(),
+            unique_id: format!("{}_outdoor", discovery.unique_id),
+            topics: OutdoorTopics {
+                device_config: format!("{prefix}/device/{object_id}/config"),
+                sensors: format!("{prefix}/sensor/{object_id}"),
+            },
+        }
+    }
+
+    pub fn device_config<'a>(&'a self, ctx: &'a MqttCtx) -> DeviceConfig<'a, SensorComponent<'a>> {
+        let availability = ctx.topics.unit_availability(self.address);
+
+        let components = SENSORS.iter()
+            .map(|sensor| (sensor.key, SensorComponent {
+                platform: sensor.platform,
+                name: sensor.name,
+                unique_id: format!("{}_{}", self.unique_id, sensor.key),
+                entity_category: "diagnostic",
+                device_class: sensor.device_class,
+                state_class: sensor.unit.map(|_| "measurement"),
+                unit_of_measurement: sensor.unit,
+                state_topic: self.topics.state(sensor),
+                availability_topic: availability.clone(),
+            }))
+            .collect();
+
+        DeviceConfig {
+            device: DeviceMapping {
+                name: &self.name,
+                ids: &self.unique_id,
+            },
+            origin: OriginMapping {
+                name: "samsunghvac-mqtt",
+            },
+            components,
+            qos: 1,
+        }
+    }
+}
+
+/// Spawns a task per sensor publishing its value as the outdoor unit
+/// notifies it. Outdoor units broadcast these regularly, so they aren't
+/// read up front
+pub fn start(ctx: &Rc<MqttCtx>) {
+    let Some(outdoor) = &ctx.outdoor else { return };
+    let scale = outdoor.scale;
+    let celsius = move |temp: Celsius| scale.decode(temp).as_float();
+
+    sensor::<message::OutdoorTemp, _>(ctx, OUTDOOR_TEMP, celsius);
+    sensor::<message::OutdoorDischargeTemp, _>(ctx, DISCHARGE_TEMP, celsius);
+    sensor::<message::OutdoorExchangerTemp, _>(ctx, EXCHANGER_TEMP, celsius);
+    sensor::<message::OutdoorDefrostStage, _>(ctx, DEFROST, |stage| stage);
+    sensor::<message::OutdoorCompressor1Running, _>(ctx, COMPRESSOR, |running| match running {
+        true => "ON",
+        false => "OFF",
+    });
+}
+
+fn sensor<M, D>(ctx: &Rc<MqttCtx>, sensor: Sensor, format: impl Fn(M::Value) -> D + 'static)
+    where M: IsMessage + 'static, M::Value: 'static, D: Display + 'static
+{
+    let Some(outdoor) = &ctx.outdoor else { return };
+
+    let mut watch = ctx.hvac.watch::<M>(outdoor.address);
+    let topic = outdoor.topics.state(&sensor);
+    let ctx = ctx.clone();
+
+    task::spawn_local(async move {
+        while let Some(value) = watch.changed().await {
+            publish(&ctx, &topic, format(value)).await;
+        }
+    });
+}
+
+#[derive(Serialize)]
+pub struct SensorComponent<'a> {
+    #[serde(rename = "p")]
+    platform: &'static str,
+    name: &'a str,
+    unique_id: String,
+    entity_category: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<&'static str>,
+    state_topic: String,
+    availability_topic: String,
+}
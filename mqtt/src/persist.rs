@@ -0,0 +1,94 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use samsunghvac_protocol::message::convert::ValueType;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time;
+
+use crate::control::{Restored, SamsungHvac, State};
+
+const SAVE_DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// On-disk form of [`State`], holding the raw protocol representation of
+/// each value
+#[derive(Serialize, Deserialize, Default)]
+struct PersistedState {
+    power: Option<u8>,
+    mode: Option<u8>,
+    fan: Option<u8>,
+    set_temp: Option<i16>,
+    current_temp: Option<i16>,
+}
+
+#[derive(Error, Debug)]
+pub enum PersistError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// Loads the last persisted state, marked as assumed. Returns `None` if
+/// nothing has been persisted yet
+pub fn load(path: &Path) -> Result<Option<State>, PersistError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let persisted: PersistedState = serde_json::from_str(&text)?;
+
+    Ok(Some(State {
+        power: persisted.power.and_then(|repr| ValueType::try_from_repr(repr).ok()),
+        mode: persisted.mode.and_then(|repr| ValueType::try_from_repr(repr).ok()),
+        fan: persisted.fan.and_then(|repr| ValueType::try_from_repr(repr).ok()),
+        set_temp: persisted.set_temp.and_then(|repr| ValueType::try_from_repr(repr).ok()),
+        current_temp: persisted.current_temp.and_then(|repr| ValueType::try_from_repr(repr).ok()),
+        restored: Restored::ALL,
+    }))
+}
+
+fn save(path: &Path, state: &State) -> Result<(), PersistError> {
+    let persisted = PersistedState {
+        power: state.power.map(|value| value.to_repr()),
+        mode: state.mode.map(|value| value.to_repr()),
+        fan: state.fan.map(|value| value.to_repr()),
+        set_temp: state.set_temp.map(|value| value.to_repr()),
+        current_temp: state.current_temp.map(|value| value.to_repr()),
+    };
+
+    let json = serde_json::to_string(&persisted)?;
+
+    // write to a temporary file and rename over the original, so a crash
+    // mid-write never leaves a truncated state file behind
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+/// Saves state to disk whenever it changes. Changes are debounced, so a
+/// burst of notifications results in a single write
+pub async fn persist_task(hvac: SamsungHvac, path: PathBuf) {
+    let mut updated = hvac.state_updated();
+
+    while updated.changed().await.is_ok() {
+        time::sleep(SAVE_DEBOUNCE).await;
+        updated.borrow_and_update();
+
+        let state = hvac.state().clone();
+
+        // nothing new to save until we've heard from the unit
+        if state.assumed() {
+            continue;
+        }
+
+        if let Err(err) = save(&path, &state) {
+            log::warn!("saving state to {}: {err}", path.display());
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rumqttc::TlsConfiguration;
+use rumqttc::tokio_rustls::rustls;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use thiserror::Error;
+
+use crate::MqttTlsConfig;
+
+#[derive(Error, Debug)]
+pub enum TlsConfigError {
+    #[error("reading {path}: {error}", path = .path.display())]
+    Read { path: PathBuf, #[source] error: io::Error },
+    #[error("client_cert and client_key must be given together")]
+    IncompleteClientAuth,
+    #[error("client_cert requires ca_file to be set")]
+    ClientAuthWithoutCa,
+    #[error("client_cert is not supported with insecure")]
+    ClientAuthInsecure,
+}
+
+pub fn configuration(config: &MqttTlsConfig) -> Result<TlsConfiguration, TlsConfigError> {
+    let client_auth = match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => Some((read(cert)?, read(key)?)),
+        (None, None) => None,
+        _ => { return Err(TlsConfigError::IncompleteClientAuth); }
+    };
+
+    if config.insecure {
+        if client_auth.is_some() {
+            return Err(TlsConfigError::ClientAuthInsecure);
+        }
+
+        log::warn!("mqtt tls: server certificate verification is disabled");
+        return Ok(insecure_configuration());
+    }
+
+    match &config.ca_file {
+        Some(ca_file) => Ok(TlsConfiguration::Simple {
+            ca: read(ca_file)?,
+            alpn: None,
+            client_auth,
+        }),
+        None if client_auth.is_some() => Err(TlsConfigError::ClientAuthWithoutCa),
+        // verify against platform root certificates:
+        None => Ok(TlsConfiguration::default()),
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, TlsConfigError> {
+    std::fs::read(path).map_err(|error| TlsConfigError::Read { path: path.to_owned(), error })
+}
+
+fn insecure_configuration() -> TlsConfiguration {
+    let provider = Arc::new(crypto::ring::default_provider());
+
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(NoServerVerification(provider)))
+        .with_no_client_auth();
+
+    TlsConfiguration::Rustls(Arc::new(config))
+}
+
+/// Accepts any server certificate, while still checking handshake
+/// signatures so the connection is at least internally consistent
+#[derive(Debug)]
+struct NoServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -10,11 +10,11 @@ pub struct NotifyCell<T> {
 }
 
 impl<T> NotifyCell<T> {
-    pub fn borrow(&self) -> Ref<T> {
+    pub fn borrow(&self) -> Ref<'_, T> {
         self.inner.borrow()
     }
 
-    pub fn borrow_mut(&self) -> NotifyCellMut<T> {
+    pub fn borrow_mut(&self) -> NotifyCellMut<'_, T> {
         let ref_ = self.inner.borrow_mut();
         NotifyCellMut { ref_, cell: self }
     }
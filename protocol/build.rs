@@ -0,0 +1,175 @@
+//! Generates the message catalog from messages.csv. See the header of that
+//! file for a description of the columns.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+const CATALOG: &str = "messages.csv";
+
+/// Types whose raw value is two's complement
+const SIGNED_TYPES: &[&str] = &["Celsius", "CelsiusLvar", "i16", "i32"];
+
+/// Types whose quantity is stored in the high 16 bits of the raw value
+const HIGH_WORD_TYPES: &[&str] = &["CelsiusLvar"];
+
+struct Entry {
+    id: u16,
+    name: String,
+    typ: String,
+    kind: &'static str,
+    unit: String,
+    scale: String,
+    description: String,
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={CATALOG}");
+
+    let csv = std::fs::read_to_string(CATALOG).expect("reading message catalog");
+    let entries = parse(&csv);
+
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    std::fs::write(out_dir.join("catalog.rs"), generate(&entries))
+        .expect("writing generated catalog");
+}
+
+fn parse(csv: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut names = HashSet::new();
+    let mut ids = HashSet::new();
+    let mut header = true;
+
+    for (idx, line) in csv.lines().enumerate() {
+        let lineno = idx + 1;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // first non-comment line is the column header
+        if header {
+            header = false;
+            continue;
+        }
+
+        // description is the last column and may itself contain commas
+        let fields = line.splitn(7, ',').map(str::trim).collect::<Vec<_>>();
+        let [id, name, typ, kind, unit, scale, description] = fields[..] else {
+            panic!("{CATALOG}:{lineno}: expected 7 fields, found {}", fields.len());
+        };
+
+        let id = id.strip_prefix("0x")
+            .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+            .unwrap_or_else(|| panic!("{CATALOG}:{lineno}: invalid id: {id}"));
+
+        let id_kind = match (id & 0x0600) >> 9 {
+            0 => "enum",
+            1 => "var",
+            2 => "lvar",
+            _ => "struct",
+        };
+
+        if kind != id_kind {
+            panic!("{CATALOG}:{lineno}: kind {kind} does not match id {id:#06x}, expected {id_kind}");
+        }
+
+        if !ids.insert(id) {
+            panic!("{CATALOG}:{lineno}: duplicate id: {id:#06x}");
+        }
+
+        if !names.insert(name.to_owned()) {
+            panic!("{CATALOG}:{lineno}: duplicate name: {name}");
+        }
+
+        if !scale.is_empty() && scale.parse::<f32>().is_err() {
+            panic!("{CATALOG}:{lineno}: invalid scale: {scale}");
+        }
+
+        entries.push(Entry {
+            id,
+            name: name.to_owned(),
+            typ: typ.to_owned(),
+            kind: id_kind,
+            unit: unit.to_owned(),
+            scale: scale.to_owned(),
+            description: description.to_owned(),
+        });
+    }
+
+    entries
+}
+
+fn generate(entries: &[Entry]) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "// generated by build.rs from {CATALOG}, do not edit").unwrap();
+    writeln!(out).unwrap();
+
+    for entry in entries {
+        let Entry { id, name, typ, .. } = entry;
+
+        match typ.as_str() {
+            "" => writeln!(out, "pub const {name}: MessageId = MessageId({id:#06x});"),
+            _ => writeln!(out, "pub type {name} = TypedMessage<{id:#06x}, {}>;", value_type(typ)),
+        }.unwrap();
+    }
+
+    writeln!(out).unwrap();
+    writeln!(out, "/// Catalog of known messages").unwrap();
+    writeln!(out, "pub static CATALOG: &[MessageMeta] = &[").unwrap();
+
+    for entry in entries {
+        let kind = match entry.kind {
+            "enum" => "Enum",
+            "var" => "Variable",
+            "lvar" => "LongVariable",
+            _ => "Structure",
+        };
+
+        let unit = optional(&entry.unit, |unit| format!("{unit:?}"));
+        let scale = optional(&entry.scale, |scale| format!("{scale}_f32"));
+        let signed = SIGNED_TYPES.contains(&inner_type(&entry.typ));
+        let shift = if HIGH_WORD_TYPES.contains(&inner_type(&entry.typ)) { 16 } else { 0 };
+
+        writeln!(out, "    MessageMeta {{ id: MessageId({id:#06x}), name: {name:?}, kind: MessageKind::{kind}, unit: {unit}, scale: {scale}, signed: {signed}, shift: {shift}, description: {description:?} }},",
+            id = entry.id,
+            name = entry.name,
+            description = entry.description,
+        ).unwrap();
+    }
+
+    writeln!(out, "];").unwrap();
+
+    out
+}
+
+const PRIMITIVE_TYPES: &[&str] = &["bool", "u8", "u16", "u32", "i16", "i32"];
+
+/// Rust type for a catalog type, which may be wrapped as `UnknownOr<T>`
+fn value_type(typ: &str) -> String {
+    if let Some(inner) = typ.strip_prefix("UnknownOr<").and_then(|typ| typ.strip_suffix('>')) {
+        format!("types::UnknownOr<{}>", value_type(inner))
+    } else if PRIMITIVE_TYPES.contains(&typ) {
+        typ.to_owned()
+    } else {
+        format!("types::{typ}")
+    }
+}
+
+/// Catalog type with any `UnknownOr<T>` wrapper removed
+fn inner_type(typ: &str) -> &str {
+    typ.strip_prefix("UnknownOr<")
+        .and_then(|typ| typ.strip_suffix('>'))
+        .map(inner_type)
+        .unwrap_or(typ)
+}
+
+fn optional(value: &str, f: impl FnOnce(&str) -> String) -> String {
+    if value.is_empty() {
+        "None".to_owned()
+    } else {
+        format!("Some({})", f(value))
+    }
+}
@@ -0,0 +1,173 @@
+//! [`Arbitrary`] implementations for fuzzing and property testing.
+//!
+//! Generated packets are always serializable: message values match the
+//! kind encoded in their id, and packets fit within a single frame.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::packet::{
+    u2, Address, Data, DataType, Message, MessageId, MessageKind, MessagesVec,
+    Packet, PacketInfo, PacketType, Structure, StructureData, Value,
+};
+
+/// Keeps generated packets within MAX_FRAME_SIZE
+const MAX_ARBITRARY_MESSAGES: usize = 64;
+const MAX_ARBITRARY_STRUCTURE: usize = 128;
+
+impl<'a> Arbitrary<'a> for Address {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Address::from_bytes(u.arbitrary()?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for MessageId {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(MessageId(u.arbitrary()?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Value {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Value::Enum(u.arbitrary()?),
+            1 => Value::Variable(u.arbitrary()?),
+            _ => Value::LongVariable(u.arbitrary()?),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Message {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        // pick an id that isn't a structure, then a value of matching kind
+        let id = loop {
+            let id = MessageId::arbitrary(u)?;
+            if id.kind() != MessageKind::Structure {
+                break id;
+            }
+        };
+
+        let value = match id.kind() {
+            MessageKind::Enum => Value::Enum(u.arbitrary()?),
+            MessageKind::Variable => Value::Variable(u.arbitrary()?),
+            MessageKind::LongVariable => Value::LongVariable(u.arbitrary()?),
+            MessageKind::Structure => unreachable!(),
+        };
+
+        Ok(Message { id, value })
+    }
+}
+
+impl<'a> Arbitrary<'a> for PacketInfo {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut info = PacketInfo::with_retry_count(u2::new(u.int_in_range(0..=3)?));
+        info.protocol_version = u2::new(u.int_in_range(0..=3)?);
+        Ok(info)
+    }
+}
+
+impl<'a> Arbitrary<'a> for PacketType {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&[
+            PacketType::StandBy,
+            PacketType::Normal,
+            PacketType::Gathering,
+            PacketType::Install,
+            PacketType::Download,
+        ])?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for DataType {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&[
+            DataType::Undefined,
+            DataType::Read,
+            DataType::Write,
+            DataType::Request,
+            DataType::Notification,
+            DataType::Response,
+            DataType::Ack,
+            DataType::Nack,
+        ])?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Structure {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let number = MessageId(u.arbitrary::<u16>()? | 0x0600);
+
+        let len = u.int_in_range(0..=MAX_ARBITRARY_STRUCTURE)?;
+        let mut data = StructureData::new();
+        for _ in 0..len {
+            data.push(u.arbitrary()?).unwrap();
+        }
+
+        Ok(Structure { number, data })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Data {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        if u.ratio(1, 8)? {
+            return Ok(Data::Structure(u.arbitrary()?));
+        }
+
+        let count = u.int_in_range(0..=MAX_ARBITRARY_MESSAGES)?;
+        let mut messages = MessagesVec::new();
+        for _ in 0..count {
+            messages.push(u.arbitrary()?).unwrap();
+        }
+
+        Ok(Data::Messages(messages))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Packet {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Packet {
+            source: u.arbitrary()?,
+            destination: u.arbitrary()?,
+            packet_info: u.arbitrary()?,
+            packet_type: u.arbitrary()?,
+            data_type: u.arbitrary()?,
+            packet_number: u.arbitrary()?,
+            data: u.arbitrary()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frame::MAX_FRAME_SIZE;
+
+    use super::*;
+
+    /// Fills `buf` with deterministic noise for `Unstructured` to draw from
+    fn noise(seed: u32, buf: &mut [u8]) {
+        let mut state = seed.max(1);
+        for byte in buf {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *byte = state as u8;
+        }
+    }
+
+    #[test]
+    fn arbitrary_packets_round_trip() {
+        let mut input = [0; 4096];
+        let mut wire = [0; MAX_FRAME_SIZE + 16];
+
+        for seed in 0..500 {
+            noise(seed, &mut input);
+            let packet = Packet::arbitrary(&mut Unstructured::new(&input)).unwrap();
+
+            let len = packet.serialize_frame(&mut wire)
+                .unwrap_or_else(|err| panic!("seed {seed}: serializing: {err}"));
+            let parsed = Packet::parse_wire(&wire[..len])
+                .unwrap_or_else(|err| panic!("seed {seed}: parsing: {err}"));
+
+            assert_eq!(parsed, packet, "seed {seed}");
+        }
+    }
+}
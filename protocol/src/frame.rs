@@ -1,17 +1,46 @@
 use core::num::NonZeroUsize;
+use core::time::Duration;
 
 use thiserror::Error;
 
+#[cfg(feature = "std")]
+mod reader;
+#[cfg(feature = "std")]
+pub use reader::FrameReader;
+
 /// Streaming frame parser
+///
+/// When a frame fails to parse, the bytes consumed since its start marker
+/// are scanned again for another start marker, as the real frame may have
+/// begun inside the bad one. This way a single corrupted byte costs one
+/// frame rather than two.
 #[derive(Default)]
 pub struct FrameParser {
     state: State,
     buffer: FrameBuffer,
+    /// bytes consumed since the start marker of the current frame
+    raw: RawBuffer,
+    /// bytes waiting to be parsed, either newly fed or put back after an
+    /// error
+    pending: PendingBuffer,
 }
 
 pub const MAX_FRAME_SIZE: usize = 1024;
 pub type FrameBuffer = heapless::Vec<u8, MAX_FRAME_SIZE>;
 
+// a frame is at most its data plus 2 size bytes, 2 CRC bytes and the end
+// marker, and pending bytes never exceed that plus the byte just fed
+const MAX_RAW_SIZE: usize = MAX_FRAME_SIZE + 5;
+type RawBuffer = heapless::Vec<u8, MAX_RAW_SIZE>;
+type PendingBuffer = heapless::Deque<u8, { MAX_RAW_SIZE + 1 }>;
+
+/// Gaps within a frame are a few byte times at most, so a partial frame
+/// followed by this much silence can be abandoned with
+/// [`FrameParser::feed_timeout`]
+pub const DEFAULT_FRAME_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Bytes sent before each frame on the wire
+pub const FRAME_PREAMBLE: [u8; 4] = [0xfd, 0xf8, 0xef, 0x7c];
 pub const FRAME_START: u8 = 0x32;
 pub const FRAME_END: u8 = 0x34;
 
@@ -25,6 +54,8 @@ pub enum FrameError {
     BadCrc { received: u16, expected: u16 },
     #[error("bad frame end marker: received {received:x?}, expected {FRAME_END}")]
     BadFrameEnd { received: u8 },
+    #[error("frame stalled after {received} bytes")]
+    Stalled { received: usize },
 }
 
 #[derive(Default)]
@@ -44,22 +75,92 @@ impl FrameParser {
         FrameParser::default()
     }
 
+    /// Feeds a byte to the parser. Completed frames and errors are
+    /// returned one at a time, so after either of those, call
+    /// [`FrameParser::resume`] until it returns `Ok(None)` to pick up any
+    /// frames recovered from the bytes of a bad one.
     pub fn feed(&mut self, byte: u8) -> Result<Option<&FrameBuffer>, FrameError> {
-        let state = core::mem::take(&mut self.state);
-        match feed_byte(state, &mut self.buffer, byte) {
-            Transition::Next(state) => {
-                self.state = state;
-                Ok(None)
-            }
-            Transition::Complete => {
-                self.state = State::Start;
-                Ok(Some(&self.buffer))
+        self.pending.push_back(byte)
+            .expect("pending bytes exceed frame size, this should never happen");
+
+        self.resume()
+    }
+
+    /// Continues parsing bytes held back from an earlier call, without
+    /// feeding a new one
+    pub fn resume(&mut self) -> Result<Option<&FrameBuffer>, FrameError> {
+        while let Some(byte) = self.pending.pop_front() {
+            let state = core::mem::take(&mut self.state);
+
+            // the start marker itself isn't kept, so that a rescan after an
+            // error always makes progress
+            if !matches!(state, State::Start) {
+                self.raw.push(byte)
+                    .expect("raw bytes exceed frame size, this should never happen");
             }
-            Transition::Error(err) => {
-                self.state = State::Start;
-                Err(err)
+
+            match feed_byte(state, &mut self.buffer, byte) {
+                Transition::Next(state) => {
+                    self.state = state;
+                }
+                Transition::Complete => {
+                    self.state = State::Start;
+                    self.raw.clear();
+                    return Ok(Some(&self.buffer));
+                }
+                Transition::Error(err) => {
+                    self.state = State::Start;
+                    self.resync();
+                    return Err(err);
+                }
             }
         }
+
+        Ok(None)
+    }
+
+    /// Number of bytes fed that haven't been parsed yet. The byte that
+    /// completed the last frame was this many bytes before the last byte
+    /// fed
+    #[cfg(feature = "std")]
+    pub(crate) fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The last completed frame
+    #[cfg(feature = "std")]
+    pub(crate) fn frame(&self) -> &FrameBuffer {
+        &self.buffer
+    }
+
+    /// Abandons a partially received frame, for callers to use when the
+    /// bus has gone quiet for longer than any gap within a frame. Without
+    /// this, a corrupted size field leaves the parser waiting on a payload
+    /// that never comes, swallowing the frames that follow.
+    ///
+    /// Unlike other errors, the abandoned bytes are dropped rather than
+    /// rescanned: a start marker among them would only stall again.
+    pub fn feed_timeout(&mut self) -> Result<(), FrameError> {
+        if matches!(self.state, State::Start) {
+            return Ok(());
+        }
+
+        let received = self.raw.len();
+        self.state = State::Start;
+        self.raw.clear();
+
+        Err(FrameError::Stalled { received })
+    }
+
+    /// Puts bytes consumed by a bad frame back in front of the pending
+    /// bytes, to be scanned again for a start marker
+    fn resync(&mut self) {
+        for byte in self.raw.iter().rev() {
+            self.pending.push_front(*byte)
+                .expect("pending bytes exceed frame size, this should never happen");
+        }
+
+        self.raw.clear();
     }
 }
 
@@ -103,7 +204,7 @@ fn feed_byte(state: State, buffer: &mut FrameBuffer, byte: u8) -> Transition {
         State::CrcLo { crc_hi } => {
             // validate crc:
             let received = u16::from_be_bytes([crc_hi, byte]);
-            let expected = crc16(&buffer);
+            let expected = crc16(buffer);
             if received != expected {
                 return Error(FrameError::BadCrc { received, expected });
             }
@@ -125,7 +226,7 @@ pub fn crc16(data: &[u8]) -> u16 {
     let mut crc = 0u16;
 
     for byte in data {
-        crc = crc ^ (u16::from(*byte) << 8);
+        crc ^= u16::from(*byte) << 8;
 
         for _ in 0..8 {
             if (crc & 0x8000) != 0 {
@@ -138,3 +239,118 @@ pub fn crc16(data: &[u8]) -> u16 {
 
     crc
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Wire = heapless::Vec<u8, 64>;
+    type Parsed = heapless::Vec<Result<FrameBuffer, FrameError>, 8>;
+
+    fn frame(data: &[u8]) -> Wire {
+        let size = u16::try_from(data.len() + 4).unwrap();
+        let crc = crc16(data);
+
+        let mut wire = Wire::new();
+        wire.push(FRAME_START).unwrap();
+        wire.extend_from_slice(&size.to_be_bytes()).unwrap();
+        wire.extend_from_slice(data).unwrap();
+        wire.extend_from_slice(&crc.to_be_bytes()).unwrap();
+        wire.push(FRAME_END).unwrap();
+        wire
+    }
+
+    /// Feeds every byte, collecting frames and errors including those
+    /// recovered by resuming
+    fn parse(bytes: &[u8]) -> Parsed {
+        let mut parser = FrameParser::new();
+        let mut parsed = Parsed::new();
+
+        for byte in bytes {
+            let mut result = parser.feed(*byte);
+
+            loop {
+                match result {
+                    Ok(None) => break,
+                    Ok(Some(frame)) => parsed.push(Ok(frame.clone())).unwrap(),
+                    Err(err) => parsed.push(Err(err)).unwrap(),
+                }
+
+                result = parser.resume();
+            }
+        }
+
+        parsed
+    }
+
+    #[test]
+    fn garbage_before_frame_is_skipped() {
+        let mut wire = Wire::from_slice(&[0x00, 0xff, FRAME_END, 0x12]).unwrap();
+        wire.extend_from_slice(&frame(b"hello")).unwrap();
+
+        let parsed = parse(&wire);
+
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].as_ref().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn bad_crc_then_valid_frame() {
+        let mut wire = frame(b"corrupt");
+        let crc = wire.len() - 2;
+        wire[crc] ^= 0xff;
+        wire.extend_from_slice(&frame(b"fine")).unwrap();
+
+        let parsed = parse(&wire);
+
+        assert_eq!(parsed.len(), 2);
+        assert!(matches!(parsed[0], Err(FrameError::BadCrc { .. })));
+        assert_eq!(parsed[1].as_ref().unwrap(), b"fine");
+    }
+
+    #[test]
+    fn truncated_frame_does_not_swallow_next() {
+        // the first frame is cut short, so its size runs into the second
+        let first = frame(b"truncated");
+        let mut wire = Wire::from_slice(&first[..first.len() - 4]).unwrap();
+        wire.extend_from_slice(&frame(b"next")).unwrap();
+
+        let parsed = parse(&wire);
+
+        let frames = parsed.iter().filter_map(|result| result.as_ref().ok()).collect::<heapless::Vec<_, 8>>();
+        assert!(parsed.iter().any(Result::is_err));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0], b"next");
+    }
+
+    #[test]
+    fn oversize_length_then_valid_frame() {
+        let size = u16::try_from(MAX_FRAME_SIZE + 5).unwrap();
+        let mut wire = Wire::from_slice(&[FRAME_START]).unwrap();
+        wire.extend_from_slice(&size.to_be_bytes()).unwrap();
+        wire.extend_from_slice(&frame(b"fine")).unwrap();
+
+        let parsed = parse(&wire);
+
+        assert_eq!(parsed.len(), 2);
+        assert!(matches!(parsed[0], Err(FrameError::FrameTooLong { size: s }) if s == size));
+        assert_eq!(parsed[1].as_ref().unwrap(), b"fine");
+    }
+
+    #[test]
+    fn feed_timeout_abandons_partial_frame() {
+        let mut parser = FrameParser::new();
+
+        // start marker and a size promising more data than ever comes
+        for byte in [FRAME_START, 0x00, 0x20, 0xaa] {
+            assert!(matches!(parser.feed(byte), Ok(None)));
+        }
+
+        assert!(matches!(parser.feed_timeout(), Err(FrameError::Stalled { received: 3 })));
+
+        // back looking for a start marker, with nothing left to abandon
+        assert!(matches!(parser.feed_timeout(), Ok(())));
+        assert!(matches!(parser.feed(0xaa), Ok(None)));
+        assert!(matches!(parser.feed_timeout(), Ok(())));
+    }
+}
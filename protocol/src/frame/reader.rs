@@ -0,0 +1,282 @@
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+use crate::frame::{FrameError, FrameParser, DEFAULT_FRAME_TIMEOUT};
+use crate::packet::{Packet, ReadPacketError};
+
+const READ_SIZE: usize = 256;
+
+/// Blocking packet reader over any [`Read`], for synchronous tools
+///
+/// A corrupt frame, unparseable packet or failed read is yielded as an
+/// error without ending iteration. Iteration ends at end of input. Offsets
+/// in packet errors count from the start of the input.
+///
+/// A read that blocks for longer than the frame timeout means the input
+/// went quiet, so a partial frame before it is abandoned with a
+/// [`FrameError::Stalled`] error.
+pub struct FrameReader<R> {
+    input: R,
+    parser: FrameParser,
+    frame_timeout: Option<Duration>,
+    buf: [u8; READ_SIZE],
+    pos: usize,
+    len: usize,
+    /// bytes fed to the parser so far
+    fed: usize,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(input: R) -> Self {
+        FrameReader {
+            input,
+            parser: FrameParser::new(),
+            frame_timeout: Some(DEFAULT_FRAME_TIMEOUT),
+            buf: [0; READ_SIZE],
+            pos: 0,
+            len: 0,
+            fed: 0,
+        }
+    }
+
+    /// How long a read may block mid-frame before the frame is abandoned,
+    /// [`DEFAULT_FRAME_TIMEOUT`] by default. `None` waits indefinitely
+    pub fn frame_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.frame_timeout = timeout;
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+}
+
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = Result<Packet, ReadPacketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // frames recovered from the bytes of a bad frame come first
+        let result = self.parser.resume().map(|frame| frame.is_some());
+        if let Some(item) = decoded(&self.parser, result, self.fed) {
+            return Some(item);
+        }
+
+        loop {
+            if self.pos == self.len {
+                // time spent blocked in read is time the input was quiet,
+                // however long the caller took between items
+                let started = Instant::now();
+
+                match self.input.read(&mut self.buf) {
+                    Ok(0) => return None,
+                    Ok(n) => {
+                        self.pos = 0;
+                        self.len = n;
+
+                        let stalled = self.frame_timeout
+                            .is_some_and(|timeout| started.elapsed() > timeout);
+
+                        if stalled && let Err(err) = self.parser.feed_timeout() {
+                            return Some(Err(err.into()));
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Some(Err(err.into())),
+                }
+            }
+
+            let byte = self.buf[self.pos];
+            self.pos += 1;
+            self.fed += 1;
+
+            let result = self.parser.feed(byte).map(|frame| frame.is_some());
+            if let Some(item) = decoded(&self.parser, result, self.fed) {
+                return Some(item);
+            }
+        }
+    }
+}
+
+/// Parses the frame `parser` just completed, if any. `fed` and the bytes
+/// still pending locate the frame in the input, so packet errors can
+/// report where in it they are
+fn decoded(parser: &FrameParser, result: Result<bool, FrameError>, fed: usize)
+    -> Option<Result<Packet, ReadPacketError>>
+{
+    match result {
+        Ok(false) => None,
+        Ok(true) => {
+            let frame = parser.frame();
+
+            // the frame ended at the byte just parsed, after its data, 2 crc
+            // bytes and the end marker
+            let end = fed - parser.pending_len() - 1;
+            let data_offset = end - 2 - frame.len();
+
+            Some(Packet::parse(frame).map_err(|err| err.offset_by(data_offset).into()))
+        }
+        Err(err) => Some(Err(err.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use crate::frame::{crc16, FRAME_END, FRAME_START};
+    use crate::packet::{addresses, Data, DataType, MessagesVec, PacketError, PacketField, PacketInfo, PacketType};
+
+    use super::*;
+
+    fn packet(packet_number: u8) -> Packet {
+        Packet {
+            source: addresses::INDOOR,
+            destination: addresses::BROADCAST,
+            packet_info: PacketInfo::default(),
+            packet_type: PacketType::Normal,
+            data_type: DataType::Notification,
+            packet_number,
+            data: Data::Messages(MessagesVec::new()),
+        }
+    }
+
+    fn wire(packets: &[Packet]) -> Vec<u8> {
+        packets.iter()
+            .flat_map(|packet| packet.serialize_to_vec().unwrap())
+            .collect()
+    }
+
+    /// Reads one byte at a time, blocking before the byte at `slow_at`
+    struct Slow<'a> {
+        data: &'a [u8],
+        slow_at: usize,
+    }
+
+    impl Read for Slow<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.slow_at == 0 {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+
+            let Some((byte, rest)) = self.data.split_first() else {
+                return Ok(0);
+            };
+
+            buf[0] = *byte;
+            self.data = rest;
+            self.slow_at = self.slow_at.wrapping_sub(1);
+            Ok(1)
+        }
+    }
+
+    /// Reads one byte at a time, failing once partway through
+    struct Flaky<'a> {
+        data: &'a [u8],
+        fail_at: usize,
+    }
+
+    impl Read for Flaky<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.fail_at == 0 {
+                self.fail_at = usize::MAX;
+                return Err(io::Error::other("flaky"));
+            }
+
+            let Some((byte, rest)) = self.data.split_first() else {
+                return Ok(0);
+            };
+
+            buf[0] = *byte;
+            self.data = rest;
+            self.fail_at -= 1;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn reads_packets_until_end_of_input() {
+        let data = wire(&[packet(1), packet(2)]);
+
+        let numbers = FrameReader::new(data.as_slice())
+            .map(|packet| packet.unwrap().packet_number)
+            .collect::<Vec<_>>();
+
+        assert_eq!(numbers, [1, 2]);
+    }
+
+    #[test]
+    fn bad_frame_does_not_end_iteration() {
+        let mut data = wire(&[packet(1)]);
+        // corrupt the CRC
+        let crc = data.len() - 2;
+        data[crc] ^= 0xff;
+        data.extend(wire(&[packet(2)]));
+
+        let mut reader = FrameReader::new(data.as_slice());
+
+        assert!(matches!(reader.next(), Some(Err(ReadPacketError::Frame(FrameError::BadCrc { .. })))));
+        assert_eq!(reader.next().unwrap().unwrap().packet_number, 2);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn packet_errors_count_from_start_of_input() {
+        let mut data = wire(&[packet(1)]);
+        let frame_start = data.len();
+
+        // a frame holding only a source address
+        let short = [0x20, 0x00, 0x00];
+        data.push(FRAME_START);
+        data.extend((short.len() as u16 + 4).to_be_bytes());
+        data.extend(short);
+        data.extend(crc16(&short).to_be_bytes());
+        data.push(FRAME_END);
+
+        let mut reader = FrameReader::new(data.as_slice());
+        assert_eq!(reader.next().unwrap().unwrap().packet_number, 1);
+
+        let Some(Err(ReadPacketError::Packet(PacketError::TooShort { field, offset }))) = reader.next() else {
+            panic!("expected the short frame to fail");
+        };
+
+        assert_eq!(field, PacketField::Destination);
+        assert_eq!(offset, frame_start + 3 + short.len());
+    }
+
+    #[test]
+    fn read_error_does_not_end_iteration() {
+        let data = wire(&[packet(1)]);
+        let mut reader = FrameReader::new(Flaky { data: &data, fail_at: 3 });
+
+        assert!(matches!(reader.next(), Some(Err(ReadPacketError::Io(_)))));
+        assert_eq!(reader.next().unwrap().unwrap().packet_number, 1);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn quiet_input_abandons_partial_frame() {
+        let data = wire(&[packet(1), packet(2)]);
+        let reader = FrameReader::new(Slow { data: &data, slow_at: 5 })
+            .frame_timeout(Some(Duration::from_millis(5)));
+
+        let items = reader.collect::<Vec<_>>();
+
+        assert!(matches!(items[0], Err(ReadPacketError::Frame(FrameError::Stalled { .. }))));
+        assert_eq!(items[1].as_ref().unwrap().packet_number, 2);
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn quiet_input_between_frames_is_fine() {
+        let first = wire(&[packet(1)]);
+        let data = wire(&[packet(1), packet(2)]);
+        let reader = FrameReader::new(Slow { data: &data, slow_at: first.len() })
+            .frame_timeout(Some(Duration::from_millis(5)));
+
+        let numbers = reader
+            .map(|packet| packet.unwrap().packet_number)
+            .collect::<Vec<_>>();
+
+        assert_eq!(numbers, [1, 2]);
+    }
+}
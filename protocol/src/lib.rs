@@ -1,5 +1,14 @@
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+
 pub mod frame;
 pub mod message;
 pub mod packet;
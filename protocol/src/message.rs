@@ -1,4 +1,4 @@
-use crate::packet::{Message, MessageId};
+use crate::packet::{Message, MessageId, MessageKind, Value};
 
 pub mod convert;
 pub mod types;
@@ -6,56 +6,128 @@ pub mod types;
 pub use convert::IsMessage;
 
 use convert::TypedMessage;
-use types::{Celsius, CelsiusLvar, FanSetting, OperationMode, PowerSetting};
-
-pub type SetTemp = TypedMessage<0x4201, Celsius>;
-pub type CurrentTemp = TypedMessage<0x4203, Celsius>;
-pub type ModifiedCurrentTemp = TypedMessage<0x4204, Celsius>;
-pub type EvaInTemp = TypedMessage<0x4205, Celsius>;
-pub type EvaOutTemp = TypedMessage<0x4206, Celsius>;
-pub type OutdoorTemp = TypedMessage<0x8204, Celsius>;
-pub type OutdoorDischargeTemp = TypedMessage<0x820a, Celsius>;
-pub type OutdoorExchangerTemp = TypedMessage<0x8218, Celsius>;
-
-pub type CoolHighTempLimit = TypedMessage<0x0411, CelsiusLvar>;
-pub type CoolLowTempLimit = TypedMessage<0x0412, CelsiusLvar>;
-pub type HeatHighTempLimit = TypedMessage<0x0413, CelsiusLvar>;
-pub type HeatLowTempLimit = TypedMessage<0x0414, CelsiusLvar>;
-
-pub type Power = TypedMessage<0x4000, PowerSetting>;
-pub type Mode = TypedMessage<0x4001, OperationMode>;
-pub type ModeReal = TypedMessage<0x4002, OperationMode>;
-pub type FanMode = TypedMessage<0x4006, FanSetting>;
+use types::TempScale;
+
+include!(concat!(env!("OUT_DIR"), "/catalog.rs"));
+
+/// Older name for [`FanMode`], from before it had a typed value
+pub const FAN_SPEED: MessageId = FanMode::ID;
+
+/// Older name for [`OutdoorCompressor1Running`]
+pub const OUTDOOR_COMP1_STATUS: MessageId = OutdoorCompressor1Running::ID;
+
+/// Catalog entry describing a known message, generated from messages.csv
+#[derive(Debug)]
+pub struct MessageMeta {
+    pub id: MessageId,
+    pub name: &'static str,
+    pub kind: MessageKind,
+    /// physical unit of the decoded value, if any
+    pub unit: Option<&'static str>,
+    /// multiplier from the raw integer value to the unit
+    pub scale: Option<f32>,
+    /// raw value is two's complement
+    pub signed: bool,
+    /// bits the quantity is shifted left by in the raw value, 16 for
+    /// types kept in the high half of a long variable
+    pub shift: u8,
+    pub description: &'static str,
+}
+
+impl MessageMeta {
+    /// Raw integer value of this message, sign extended if the message
+    /// is signed
+    pub fn integer(&self, value: Value) -> i64 {
+        if self.signed {
+            i64::from(value.as_i32())
+        } else {
+            i64::from(value.as_u32())
+        }
+    }
+
+    /// Converts a raw value of this message to its physical quantity,
+    /// if the message has a scale
+    pub fn scaled(&self, value: Value) -> Option<f32> {
+        let quantity = self.integer(value) >> self.shift;
+        Some(quantity as f32 * self.scale?)
+    }
+
+    /// Like [`MessageMeta::scaled`], converting temperatures from a unit
+    /// that sends them in `scale` to celsius
+    pub fn scaled_in(&self, value: Value, scale: TempScale) -> Option<f32> {
+        let scaled = self.scaled(value)?;
+
+        match self.unit {
+            Some("°C") => Some(scale.decode_float(scaled)),
+            _ => Some(scaled),
+        }
+    }
+}
 
 pub fn new<M: IsMessage>(value: M::Value) -> Message {
     M::new(value).to_message()
 }
 
-pub type UnknownTemp4202 = TypedMessage<0x4202, Celsius>;
-pub type UnknownTemp42df = TypedMessage<0x42df, Celsius>;
-pub type UnknownTemp42e0 = TypedMessage<0x42e0, Celsius>;
-pub type UnknownTemp42e1 = TypedMessage<0x42e1, Celsius>;
-pub type UnknownTemp42e2 = TypedMessage<0x42e2, Celsius>;
-pub type UnknownTemp42e3 = TypedMessage<0x42e3, Celsius>;
-pub type UnknownTemp42e4 = TypedMessage<0x42e4, Celsius>;
-pub type UnknownTemp8254 = TypedMessage<0x8254, Celsius>;
-pub type UnknownTemp8280 = TypedMessage<0x8280, Celsius>;
-pub type UnknownTemp82a1 = TypedMessage<0x82a1, Celsius>;
-pub type UnknownTemp82e3 = TypedMessage<0x82e3, Celsius>;
-pub type UnknownTemp82e5 = TypedMessage<0x82e5, Celsius>;
-pub type UnknownTemp82e6 = TypedMessage<0x82e6, Celsius>;
-pub type UnknownTemp82eb = TypedMessage<0x82eb, Celsius>;
-pub type UnknownTemp82ec = TypedMessage<0x82ec, Celsius>;
-
-pub const FAN_SPEED: MessageId = MessageId(0x4006);
-pub const FAN_MODE_REAL: MessageId = MessageId(0x4007);
-pub const THERMO: MessageId = MessageId(0x4028);
-pub const DEFROST: MessageId = MessageId(0x402e);
-pub const USE_SILENCE: MessageId = MessageId(0x4045);
-pub const CONTROL_SILENCE: MessageId = MessageId(0x4046);
-pub const OUTDOOR_SERVICE_MODE: MessageId = MessageId(0x8000);
-pub const OUTDOOR_DRIVE_MODE: MessageId = MessageId(0x8001);
-pub const OUTDOOR_MODE: MessageId = MessageId(0x8003);
-pub const OUTDOOR_COMP1_STATUS: MessageId = MessageId(0x8010);
-pub const OUTDOOR_4WAY_STATUS: MessageId = MessageId(0x801a);
-pub const INDOOR_DEFROST_STAGE: MessageId = MessageId(0x8061);
+/// Tests whether a [`Message`] is message `M` with a value matching a
+/// pattern, with an optional guard as in [`matches!`]:
+///
+/// ```ignore
+/// if matches_message!(msg, message::Power => PowerSetting::On) { ... }
+/// if matches_message!(msg, message::SetTemp => temp if temp.as_float() > 25.0) { ... }
+/// ```
+#[macro_export]
+macro_rules! matches_message {
+    ($msg:expr, $message:path => $pattern:pat $(if $guard:expr)? $(,)?) => {
+        matches!(
+            <$message as $crate::message::IsMessage>::get($msg),
+            Some($pattern) $(if $guard)?
+        )
+    };
+}
+
+/// Looks up a message by symbolic name, case insensitive
+pub fn lookup_name(name: &str) -> Option<MessageId> {
+    CATALOG.iter()
+        .find(|meta| meta.name.eq_ignore_ascii_case(name))
+        .map(|meta| meta.id)
+}
+
+/// Returns the symbolic name of a known message
+pub fn name_of(id: MessageId) -> Option<&'static str> {
+    id.metadata().map(|meta| meta.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::types::{Celsius, CelsiusLvar};
+    use super::*;
+
+    #[test]
+    fn scaled_long_variable_temperature() {
+        let meta = CoolHighTempLimit::ID.metadata().unwrap();
+
+        let msg = new::<CoolHighTempLimit>(CelsiusLvar::from_float(30.0));
+        assert_eq!(meta.scaled(msg.value), Some(30.0));
+
+        let msg = new::<CoolHighTempLimit>(CelsiusLvar::from_float(-5.5));
+        assert_eq!(meta.scaled(msg.value), Some(-5.5));
+    }
+
+    #[test]
+    fn scaled_variable_temperature() {
+        let meta = SetTemp::ID.metadata().unwrap();
+        let msg = new::<SetTemp>(Celsius::from_float(-12.5));
+        assert_eq!(meta.scaled(msg.value), Some(-12.5));
+    }
+
+    #[test]
+    fn scaled_in_converts_temperatures_only() {
+        let meta = SetTemp::ID.metadata().unwrap();
+        let msg = new::<SetTemp>(Celsius::from_float(212.0));
+        assert_eq!(meta.scaled_in(msg.value, TempScale::Fahrenheit), Some(100.0));
+        assert_eq!(meta.scaled_in(msg.value, TempScale::Celsius), Some(212.0));
+
+        let meta = MessageMeta { unit: Some("Hz"), ..*meta };
+        assert_eq!(meta.scaled_in(msg.value, TempScale::Fahrenheit), Some(212.0));
+    }
+}
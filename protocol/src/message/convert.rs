@@ -92,3 +92,23 @@ impl ValueRepr for u32 {
         Value::LongVariable(*self)
     }
 }
+
+/// Two's complement variable
+impl ValueRepr for i16 {
+    fn try_from_value(value: Value) -> Result<Self, WrongValueKind> {
+        value.expect_u16().map(|value| value as i16)
+    }
+    fn to_value(&self) -> Value {
+        Value::Variable(*self as u16)
+    }
+}
+
+/// Two's complement long variable
+impl ValueRepr for i32 {
+    fn try_from_value(value: Value) -> Result<Self, WrongValueKind> {
+        value.expect_u32().map(|value| value as i32)
+    }
+    fn to_value(&self) -> Value {
+        Value::LongVariable(*self as u32)
+    }
+}
@@ -1,4 +1,5 @@
 use core::convert::Infallible;
+use core::str::FromStr;
 
 use derive_more::Display;
 use thiserror::Error;
@@ -12,10 +13,16 @@ pub struct EnumOutOfRange {
     pub value: u8,
 }
 
-// Celcius
+#[derive(Debug, Error)]
+#[error("unknown variant for {enum_name}")]
+pub struct UnknownVariant {
+    pub enum_name: &'static str,
+}
+
+// Celcius, in signed tenths of a degree
 #[derive(Display, PartialEq, PartialOrd, Eq, Ord, Clone, Copy)]
 #[display("{:.1} °C", self.as_float())]
-pub struct Celsius(u16);
+pub struct Celsius(i16);
 
 impl Celsius {
     pub fn from_float(temp: f32) -> Self {
@@ -35,13 +42,13 @@ impl From<CelsiusLvar> for Celsius {
 
 impl ValueType for Celsius {
     type Err = Infallible;
-    type Repr = u16;
+    type Repr = i16;
 
-    fn try_from_repr(value: u16) -> Result<Self, Infallible> {
+    fn try_from_repr(value: i16) -> Result<Self, Infallible> {
         Ok(Celsius(value))
     }
 
-    fn to_repr(&self) -> u16 {
+    fn to_repr(&self) -> i16 {
         self.0
     }
 }
@@ -50,7 +57,7 @@ impl ValueType for Celsius {
 #[display("{:.1} °C", self.as_float())]
 /// This is a celsius value, but represented in the high 16 bits of a
 /// 32 bit long variable for some reason
-pub struct CelsiusLvar(u16);
+pub struct CelsiusLvar(i16);
 
 impl CelsiusLvar {
     pub fn from_float(temp: f32) -> Self {
@@ -67,20 +74,68 @@ impl ValueType for CelsiusLvar {
     type Repr = u32;
 
     fn try_from_repr(value: u32) -> Result<Self, Infallible> {
-        let value = (value >> 16) as u16;
+        let value = (value >> 16) as u16 as i16;
         Ok(CelsiusLvar(value))
     }
 
     fn to_repr(&self) -> u32 {
-        (self.0 as u32) << 16
+        (self.0 as u16 as u32) << 16
+    }
+}
+
+/// Scale a unit puts temperatures on the wire in. Temperature messages are
+/// typed as [`Celsius`], but some US-market units send and expect tenths
+/// of a degree Fahrenheit in the same fields
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TempScale {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl TempScale {
+    /// Converts a temperature as sent by a unit using this scale
+    pub fn decode(self, wire: Celsius) -> Celsius {
+        Celsius::from_float(self.decode_float(wire.as_float()))
+    }
+
+    /// Like [`TempScale::decode`], for temperatures already scaled to
+    /// degrees, eg. by [`MessageMeta::scaled`](crate::message::MessageMeta::scaled)
+    pub fn decode_float(self, wire: f32) -> f32 {
+        match self {
+            TempScale::Celsius => wire,
+            TempScale::Fahrenheit => (wire - 32.0) * 5.0 / 9.0,
+        }
+    }
+
+    /// Converts a temperature to how a unit using this scale expects it
+    pub fn encode(self, temp: Celsius) -> Celsius {
+        match self {
+            TempScale::Celsius => temp,
+            TempScale::Fahrenheit => Celsius::from_float(temp.as_float() * 9.0 / 5.0 + 32.0),
+        }
+    }
+}
+
+impl FromStr for TempScale {
+    type Err = UnknownVariant;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("celsius") || s.eq_ignore_ascii_case("c") {
+            Ok(TempScale::Celsius)
+        } else if s.eq_ignore_ascii_case("fahrenheit") || s.eq_ignore_ascii_case("f") {
+            Ok(TempScale::Fahrenheit)
+        } else {
+            Err(UnknownVariant { enum_name: "TempScale" })
+        }
     }
 }
 
-fn decis_from_float(value: f32) -> u16 {
-    f32::round(value * 10.0) as u16
+fn decis_from_float(value: f32) -> i16 {
+    f32::round(value * 10.0) as i16
 }
 
-fn float_from_decis(decis: u16) -> f32 {
+fn float_from_decis(decis: i16) -> f32 {
     decis as f32 / 10.0
 }
 
@@ -110,6 +165,66 @@ macro_rules! define_enum {
                 *self as u8
             }
         }
+
+        define_enum!(@common $name { $( $variant, )+ });
+    };
+
+    // enums with a catch-all variant preserve unknown values rather than
+    // failing to deserialize:
+    { enum $name:ident { $( $variant:ident = $value:expr, )+ _ => $other:ident, } } => {
+        #[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+        #[display("{:?}", self)]
+        pub enum $name {
+            $(
+                $variant,
+            )+
+            $other(u8),
+        }
+
+        impl ValueType for $name {
+            type Err = Infallible;
+            type Repr = u8;
+
+            fn try_from_repr(repr: u8) -> Result<Self, Self::Err> {
+                match repr {
+                    $( $value => Ok($name::$variant), )+
+                    _ => Ok($name::$other(repr)),
+                }
+            }
+
+            fn to_repr(&self) -> u8 {
+                match self {
+                    $( $name::$variant => $value, )+
+                    $name::$other(repr) => *repr,
+                }
+            }
+        }
+
+        define_enum!(@common $name { $( $variant, )+ });
+    };
+
+    { @common $name:ident { $( $variant:ident, )+ } } => {
+        impl $name {
+            /// All named variants of this enum
+            pub const VARIANTS: &'static [$name] = &[ $( $name::$variant, )+ ];
+        }
+
+        /// Parses a variant name (case insensitive) or its numeric value
+        impl FromStr for $name {
+            type Err = UnknownVariant;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $(
+                    if s.eq_ignore_ascii_case(stringify!($variant)) {
+                        return Ok($name::$variant);
+                    }
+                )+
+
+                s.parse::<u8>().ok()
+                    .and_then(|repr| Self::try_from_repr(repr).ok())
+                    .ok_or(UnknownVariant { enum_name: stringify!($name) })
+            }
+        }
     };
 }
 
@@ -132,6 +247,7 @@ define_enum! {
         AutoDry = 12,
         AutoFan = 13,
         AutoHeat = 14,
+        _ => Other,
     }
 }
 
@@ -144,6 +260,118 @@ define_enum! {
     }
 }
 
+define_enum! {
+    enum QuietMode {
+        Off = 0,
+        On = 1,
+        _ => Other,
+    }
+}
+
+// the alternative mode selects sleep and other presets. which values
+// beyond sleep a unit accepts depends on the model, so they're kept raw:
+define_enum! {
+    enum AltMode {
+        Off = 0,
+        Sleep = 1,
+        _ => Other,
+    }
+}
+
+// fixed blade positions count from the top for vertical louvers and from
+// the left for horizontal ones:
+define_enum! {
+    enum LouverPosition {
+        Off = 0,
+        Position1 = 1,
+        Position2 = 2,
+        Position3 = 3,
+        Position4 = 4,
+        Position5 = 5,
+        Position6 = 6,
+        _ => Other,
+    }
+}
+
+define_enum! {
+    enum ThermoState {
+        Off = 0,
+        On = 1,
+    }
+}
+
+define_enum! {
+    enum DefrostStage {
+        Off = 0,
+        Stage1 = 1,
+        Stage2 = 2,
+        Stage3 = 3,
+        Stage4 = 4,
+        Finished = 7,
+        _ => Other,
+    }
+}
+
+impl DefrostStage {
+    /// Whether a defrost is in progress, rather than off or finished
+    pub fn is_active(&self) -> bool {
+        matches!(self,
+            DefrostStage::Stage1 | DefrostStage::Stage2 | DefrostStage::Stage3 | DefrostStage::Stage4)
+    }
+}
+
+/// Wraps a value type, preserving raw values it can't represent rather
+/// than failing to deserialize. Used in the catalog as `UnknownOr<T>`, for
+/// messages whose type has no catch-all variant of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownOr<T: ValueType> {
+    Known(T),
+    Unknown(T::Repr),
+}
+
+impl<T: ValueType> UnknownOr<T> {
+    pub fn known(self) -> Option<T> {
+        match self {
+            UnknownOr::Known(value) => Some(value),
+            UnknownOr::Unknown(_) => None,
+        }
+    }
+}
+
+impl<T: ValueType> From<T> for UnknownOr<T> {
+    fn from(value: T) -> Self {
+        UnknownOr::Known(value)
+    }
+}
+
+impl<T: ValueType + core::fmt::Display> core::fmt::Display for UnknownOr<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            UnknownOr::Known(value) => value.fmt(f),
+            UnknownOr::Unknown(repr) => write!(f, "Unknown({repr})"),
+        }
+    }
+}
+
+impl<T: ValueType> ValueType for UnknownOr<T> {
+    type Err = Infallible;
+    type Repr = T::Repr;
+
+    fn try_from_repr(repr: T::Repr) -> Result<Self, Infallible> {
+        Ok(match T::try_from_repr(repr) {
+            Ok(value) => UnknownOr::Known(value),
+            Err(_) => UnknownOr::Unknown(repr),
+        })
+    }
+
+    fn to_repr(&self) -> T::Repr {
+        match self {
+            UnknownOr::Known(value) => value.to_repr(),
+            UnknownOr::Unknown(repr) => *repr,
+        }
+    }
+}
+
 impl ValueType for bool {
     type Err = EnumOutOfRange;
     type Repr = u8;
@@ -160,3 +388,133 @@ impl ValueType for bool {
         *self as u8
     }
 }
+
+/// Byte of packed flags, for enum messages that carry several values in
+/// one byte, so consumers can read and set them by bit number
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+#[display("{:#010b}", self.0)]
+pub struct BitsValue(pub u8);
+
+impl BitsValue {
+    pub fn get(&self, bit: u8) -> bool {
+        self.0 & (1 << bit) != 0
+    }
+
+    pub fn set(&mut self, bit: u8, value: bool) {
+        if value {
+            self.0 |= 1 << bit;
+        } else {
+            self.0 &= !(1 << bit);
+        }
+    }
+}
+
+impl ValueType for BitsValue {
+    type Err = Infallible;
+    type Repr = u8;
+
+    fn try_from_repr(repr: u8) -> Result<Self, Infallible> {
+        Ok(BitsValue(repr))
+    }
+
+    fn to_repr(&self) -> u8 {
+        self.0
+    }
+}
+
+/// Plain numeric values, for messages without a more specific type
+macro_rules! define_raw {
+    ($($ty:ty),*) => {$(
+        impl ValueType for $ty {
+            type Err = Infallible;
+            type Repr = $ty;
+
+            fn try_from_repr(repr: $ty) -> Result<Self, Infallible> {
+                Ok(repr)
+            }
+
+            fn to_repr(&self) -> $ty {
+                *self
+            }
+        }
+    )*};
+}
+
+define_raw!(u8, u16, u32, i16, i32);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fahrenheit_temperatures() {
+        let scale = TempScale::Fahrenheit;
+
+        assert!(scale.decode(Celsius::from_float(72.0)) == Celsius::from_float(22.2));
+        assert!(scale.decode(Celsius::from_float(-40.0)) == Celsius::from_float(-40.0));
+        assert!(scale.encode(Celsius::from_float(22.0)) == Celsius::from_float(71.6));
+        assert!(scale.encode(Celsius::from_float(0.0)) == Celsius::from_float(32.0));
+
+        // tenths of a degree in each scale don't line up, but round trips
+        // land within one
+        for decis in -300..500 {
+            let temp = Celsius::from_float(decis as f32 / 10.0);
+            let round_trip = scale.decode(scale.encode(temp));
+            assert!((round_trip.as_float() - temp.as_float()).abs() <= 0.1, "{temp}");
+        }
+    }
+
+    #[test]
+    fn celsius_temperatures_pass_through() {
+        let temp = Celsius::from_float(21.5);
+        assert!(TempScale::Celsius.encode(temp) == temp);
+        assert!(TempScale::Celsius.decode(temp) == temp);
+        assert_eq!(TempScale::Celsius.decode_float(21.5), 21.5);
+    }
+
+    #[test]
+    fn unknown_or_keeps_unrepresentable_values() {
+        let known = UnknownOr::<ThermoState>::try_from_repr(1).unwrap();
+        assert_eq!(known, UnknownOr::Known(ThermoState::On));
+        assert_eq!(known.known(), Some(ThermoState::On));
+        assert_eq!(known.to_repr(), 1);
+
+        let unknown = UnknownOr::<ThermoState>::try_from_repr(7).unwrap();
+        assert_eq!(unknown, UnknownOr::Unknown(7));
+        assert_eq!(unknown.known(), None);
+        assert_eq!(unknown.to_repr(), 7);
+    }
+
+    #[test]
+    fn unknown_or_from_known_value() {
+        assert_eq!(UnknownOr::from(FanSetting::High), UnknownOr::Known(FanSetting::High));
+        assert_eq!(UnknownOr::from(FanSetting::High).to_repr(), 3);
+    }
+
+    #[test]
+    fn alt_mode_keeps_presets_raw() {
+        assert_eq!(AltMode::try_from_repr(1).unwrap(), AltMode::Sleep);
+        assert_eq!(AltMode::try_from_repr(9).unwrap(), AltMode::Other(9));
+        assert_eq!(AltMode::Other(9).to_repr(), 9);
+    }
+
+    #[test]
+    fn louver_positions() {
+        assert_eq!(LouverPosition::try_from_repr(3).unwrap(), LouverPosition::Position3);
+        assert_eq!(LouverPosition::try_from_repr(9).unwrap(), LouverPosition::Other(9));
+        assert_eq!(LouverPosition::Other(9).to_repr(), 9);
+        assert_eq!("position2".parse::<LouverPosition>().unwrap(), LouverPosition::Position2);
+    }
+
+    #[test]
+    fn bits_round_trip() {
+        let mut bits = BitsValue::try_from_repr(0b1000_0001).unwrap();
+        assert!(bits.get(0));
+        assert!(!bits.get(1));
+        assert!(bits.get(7));
+
+        bits.set(0, false);
+        bits.set(3, true);
+        assert_eq!(bits.to_repr(), 0b1000_1000);
+    }
+}
@@ -1,19 +1,30 @@
 use core::str::FromStr;
+use core::time::Duration;
 
 use derive_more::{Debug, Display};
 
 use thiserror::Error;
 pub use ux::{u1, u2, u3, u4};
 
-use crate::frame::{crc16, FRAME_END, FRAME_START};
+use crate::frame::{crc16, FrameError, FrameParser, FRAME_END, FRAME_PREAMBLE, FRAME_START, MAX_FRAME_SIZE};
+use crate::message::{IsMessage, MessageMeta};
+
+pub mod addresses;
 
 pub const MAX_MESSAGE_COUNT: usize = u8::MAX as usize;
 pub const MAX_STRUCTURE_SIZE: usize = 256;
 
+/// Bytes of packet before the payload: source, destination, packet info,
+/// packet/data type, packet number and message count
+const HEADER_SIZE: usize = 3 + 3 + 1 + 1 + 1 + 1;
+
+/// Most payload bytes that fit in one frame after the packet header
+pub const MAX_PAYLOAD_SIZE: usize = MAX_FRAME_SIZE - HEADER_SIZE;
+
 pub type MessagesVec = heapless::Vec<Message, MAX_MESSAGE_COUNT>;
 pub type StructureData = heapless::Vec<u8, MAX_STRUCTURE_SIZE>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Packet {
     pub source: Address,
     pub destination: Address,
@@ -24,28 +35,172 @@ pub struct Packet {
     pub data: Data,
 }
 
-#[derive(Debug)]
+/// Metadata about a received packet, carried alongside it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PacketMeta {
+    /// monotonic time of reception, relative to an arbitrary epoch
+    /// chosen by the receiver
+    pub received_at: Option<Duration>,
+    /// identifies the transport or peer the packet was received from
+    pub transport_id: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::large_enum_variant)]
 pub enum Data {
     Messages(MessagesVec),
     Structure(Structure),
 }
 
+impl Data {
+    /// Messages in this packet, empty for a structure
+    pub fn messages(&self) -> &[Message] {
+        match self {
+            Data::Messages(messages) => messages,
+            Data::Structure(_) => &[],
+        }
+    }
+
+    /// Value of the first message with the given id
+    pub fn get(&self, id: MessageId) -> Option<Value> {
+        self.messages().iter()
+            .find(|msg| msg.id == id)
+            .map(|msg| msg.value)
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, Message> {
+        self.messages().iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Data {
+    type Item = &'a Message;
+    type IntoIter = core::slice::Iter<'a, Message>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Sorts messages by id and removes duplicate ids, keeping the last
+/// occurrence of each. Some units reject packets containing the same id
+/// twice, and a stable order keeps serialized packets comparable.
+pub fn normalize_messages(messages: &mut MessagesVec) {
+    let mut idx = 0;
+
+    while idx < messages.len() {
+        let id = messages[idx].id;
+
+        if messages[idx + 1..].iter().any(|msg| msg.id == id) {
+            messages.remove(idx);
+        } else {
+            idx += 1;
+        }
+    }
+
+    // ids are unique by now, so an unstable sort is deterministic
+    messages.sort_unstable_by_key(|msg| msg.id);
+}
+
+/// Error parsing a packet. Offsets count from the start of the packet
+/// data, readers of captures shift them to count from the start of the
+/// capture with [`PacketError::offset_by`]
 #[derive(Debug, Error)]
 pub enum PacketError {
     /// reached end of packet while reading data
-    #[error("unexpected end of packet")]
-    TooShort,
+    #[error("unexpected end of packet reading {field} at offset {offset}")]
+    TooShort { field: PacketField, offset: usize },
     /// unknown packet type
     #[error("unknown packet type: {0}")]
     UnknownPacketType(u4),
     /// encounted a structure unexpectedly - structures may only appear
     /// as the sole message in a packet
-    #[error("invalid structure in packet payload")]
-    UnexpectedStructure,
+    #[error("invalid structure in packet payload at offset {offset}")]
+    UnexpectedStructure { offset: usize },
     #[error("structure too large: {size}")]
     StructureTooLong { size: usize },
 }
 
+impl PacketError {
+    /// Moves the offset this error reports by `base`, the position the
+    /// packet data starts at in some larger input
+    pub fn offset_by(self, base: usize) -> Self {
+        match self {
+            PacketError::TooShort { field, offset } => {
+                PacketError::TooShort { field, offset: base + offset }
+            }
+            PacketError::UnexpectedStructure { offset } => {
+                PacketError::UnexpectedStructure { offset: base + offset }
+            }
+            err => err,
+        }
+    }
+}
+
+/// Field of a packet being read, for error reporting. Message indexes
+/// count from zero.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum PacketField {
+    #[display("source address")]
+    Source,
+    #[display("destination address")]
+    Destination,
+    #[display("packet info")]
+    PacketInfo,
+    #[display("packet type")]
+    PacketType,
+    #[display("packet number")]
+    PacketNumber,
+    #[display("message count")]
+    MessageCount,
+    #[display("id of message {_0}")]
+    MessageId(u8),
+    #[display("value of message {_0}")]
+    MessageValue(u8),
+}
+
+/// Error reading a packet from a stream of frames. Frame and packet errors
+/// don't end the stream, the next frame may well be fine
+#[derive(Debug, Error)]
+pub enum ReadPacketError {
+    #[error(transparent)]
+    Frame(#[from] FrameError),
+    #[error(transparent)]
+    Packet(#[from] PacketError),
+    /// the underlying reader failed, see [`FrameReader`](crate::frame::FrameReader)
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum ParseWireError {
+    /// wire data does not begin with the frame start marker
+    #[error("missing frame start marker")]
+    MissingFrameStart,
+    /// wire data ended before the frame was complete
+    #[error("incomplete frame")]
+    Incomplete,
+    /// wire data continues past the frame end marker
+    #[error("trailing data after frame end")]
+    TrailingData,
+    #[error(transparent)]
+    Frame(#[from] FrameError),
+    #[error(transparent)]
+    Packet(#[from] PacketError),
+}
+
+impl ParseWireError {
+    /// Moves the offset a packet error reports by `base`, see
+    /// [`PacketError::offset_by`]
+    pub fn offset_by(self, base: usize) -> Self {
+        match self {
+            ParseWireError::Packet(err) => ParseWireError::Packet(err.offset_by(base)),
+            err => err,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum SerializePacketError {
     /// reached end of buffer while writing packet
@@ -55,25 +210,25 @@ pub enum SerializePacketError {
     #[error("packet message contains value of wrong type")]
     InvalidMessageValue,
     /// written packet would be too large to transmit
-    #[error("packet too long for wire")]
-    PacketTooLong,
+    #[error("packet too long: {size} bytes exceeds max {MAX_FRAME_SIZE}")]
+    PacketTooLong { size: usize },
 }
 
 impl Packet {
     pub fn parse(data: &[u8]) -> Result<Self, PacketError> {
         let mut reader = PacketReader::new(data);
 
-        let source = Address::from_bytes(reader.read_array()?);
-        let destination = Address::from_bytes(reader.read_array()?);
+        let source = Address::from_bytes(reader.read_array(PacketField::Source)?);
+        let destination = Address::from_bytes(reader.read_array(PacketField::Destination)?);
 
-        let packet_info = PacketInfo::from_byte(reader.read_u8()?);
+        let packet_info = PacketInfo::from_byte(reader.read_u8(PacketField::PacketInfo)?);
 
-        let byte = reader.read_u8()?;
+        let byte = reader.read_u8(PacketField::PacketType)?;
         let packet_type = PacketType::from_u4(u4::new(byte >> 4))?;
         let data_type = DataType::from_u4(u4::new(byte & 0xf));
 
-        let packet_number = reader.read_u8()?;
-        let message_count = reader.read_u8()?;
+        let packet_number = reader.read_u8(PacketField::PacketNumber)?;
+        let message_count = reader.read_u8(PacketField::MessageCount)?;
 
         let data = read_payload(message_count, &mut reader)?;
 
@@ -88,15 +243,77 @@ impl Packet {
         })
     }
 
+    /// Parses a single complete frame as found on the wire, the inverse of
+    /// [`Packet::serialize_frame`]. The preamble is optional, but the frame
+    /// must begin with the start marker and end exactly at the end marker.
+    /// Offsets in packet errors count from the start of `wire`.
+    pub fn parse_wire(wire: &[u8]) -> Result<Self, ParseWireError> {
+        let framed = wire.strip_prefix(&FRAME_PREAMBLE).unwrap_or(wire);
+
+        // packet data follows the start marker and 2 size bytes
+        let data_offset = wire.len() - framed.len() + 3;
+        let wire = framed;
+
+        if wire.first() != Some(&FRAME_START) {
+            return Err(ParseWireError::MissingFrameStart);
+        }
+
+        let mut parser = FrameParser::new();
+
+        for (idx, byte) in wire.iter().enumerate() {
+            if let Some(frame) = parser.feed(*byte)? {
+                if idx + 1 != wire.len() {
+                    return Err(ParseWireError::TrailingData);
+                }
+
+                return Packet::parse(frame)
+                    .map_err(|err| ParseWireError::Packet(err.offset_by(data_offset)));
+            }
+        }
+
+        Err(ParseWireError::Incomplete)
+    }
+
+    /// Exact length of the frame written by [`Packet::serialize_frame`],
+    /// including preamble
+    pub fn serialized_len(&self) -> usize {
+        // preamble, start marker, length, crc and end marker:
+        FRAME_PREAMBLE.len() + 1 + 2 + self.data_len() + 2 + 1
+    }
+
+    /// Length of the packet within its frame, which the frame parser
+    /// accepts up to [`MAX_FRAME_SIZE`]
+    fn data_len(&self) -> usize {
+        let payload = match &self.data {
+            Data::Messages(messages) => messages.iter()
+                .map(|message| message.value.kind().message_size().unwrap_or_default())
+                .sum(),
+            Data::Structure(structure) => 2 + structure.data.len(),
+        };
+
+        HEADER_SIZE + payload
+    }
+
+    /// Serializes a frame into a vec of exactly the right size
+    #[cfg(feature = "alloc")]
+    pub fn serialize_to_vec(&self) -> Result<alloc::vec::Vec<u8>, SerializePacketError> {
+        let mut out = alloc::vec![0; self.serialized_len()];
+        let n = self.serialize_frame(&mut out)?;
+        out.truncate(n);
+        Ok(out)
+    }
+
     pub fn serialize_frame(&self, out: &mut [u8]) -> Result<usize, SerializePacketError> {
+        // refuse packets no receiver would accept
+        let size = self.data_len();
+        if size > MAX_FRAME_SIZE {
+            return Err(SerializePacketError::PacketTooLong { size });
+        }
+
         // start frame
         let mut writer = PacketWriter::new(out);
 
-        writer.write_u8(0xfd)?;
-        writer.write_u8(0xf8)?;
-        writer.write_u8(0xef)?;
-        writer.write_u8(0x7c)?;
-
+        writer.write_array(FRAME_PREAMBLE)?;
         writer.write_u8(FRAME_START)?;
 
         // write zero for length, we'll fill it in later
@@ -114,7 +331,7 @@ impl Packet {
 
         // frame length on the wire includes length field and crc
         let wire_len = u16::try_from(writer.pos - len_pos)
-            .map_err(|_| SerializePacketError::PacketTooLong)?;
+            .expect("frame length checked above");
 
         // fix up frame length
         writer.buff[len_pos..][..2].copy_from_slice(&u16::to_be_bytes(wire_len));
@@ -178,14 +395,15 @@ fn read_payload(message_count: u8, reader: &mut PacketReader) -> Result<Data, Pa
     let mut messages = MessagesVec::new();
 
     for i in 0..message_count {
-        let number = MessageId(reader.read_u16()?);
+        let number = MessageId(reader.read_u16(PacketField::MessageId(i))?);
+        let field = PacketField::MessageValue(i);
         let value = match number.kind() {
-            MessageKind::Enum => Value::Enum(reader.read_u8()?),
-            MessageKind::Variable => Value::Variable(reader.read_u16()?),
-            MessageKind::LongVariable => Value::LongVariable(reader.read_u32()?),
+            MessageKind::Enum => Value::Enum(reader.read_u8(field)?),
+            MessageKind::Variable => Value::Variable(reader.read_u16(field)?),
+            MessageKind::LongVariable => Value::LongVariable(reader.read_u32(field)?),
             MessageKind::Structure => {
                 if i != 0 || message_count != 1 {
-                    return Err(PacketError::UnexpectedStructure);
+                    return Err(PacketError::UnexpectedStructure { offset: reader.offset - 2 });
                 }
 
                 let data = StructureData::from_slice(reader.data)
@@ -224,12 +442,42 @@ impl Address {
             address: bytes[2],
         }
     }
+
+    /// Packs the address into the low 24 bits of a u32, class first, as
+    /// used by other NASA tooling
+    pub fn to_u32(&self) -> u32 {
+        u32::from_be_bytes([0, self.class, self.channel, self.address])
+    }
+
+    /// Inverse of [`Address::to_u32`], failing if any of the high 8 bits
+    /// are set
+    pub fn from_u32(value: u32) -> Result<Self, InvalidAddress> {
+        let [high, class, channel, address] = value.to_be_bytes();
+
+        if high != 0 {
+            return Err(InvalidAddress);
+        }
+
+        Ok(Address { class, channel, address })
+    }
+
+    /// Displays the address in compact form, eg. `201000`
+    pub fn compact(self) -> CompactAddress {
+        CompactAddress(self)
+    }
 }
 
+/// Compact form of an [`Address`], without separators
+#[derive(Debug, Display, PartialEq, Eq, Hash, Clone, Copy)]
+#[display("{:06x}", self.0.to_u32())]
+pub struct CompactAddress(pub Address);
+
 #[derive(Display, Debug)]
 #[display("invalid address")]
 pub struct InvalidAddress;
 
+/// Parses an address in either dotted (`20.10.00`) or compact (`201000`)
+/// form
 impl FromStr for Address {
     type Err = InvalidAddress;
 
@@ -238,6 +486,14 @@ impl FromStr for Address {
             return Err(InvalidAddress);
         }
 
+        if s.len() == 6 {
+            return Ok(Address {
+                class: parse_hex(&s[0..2])?,
+                channel: parse_hex(&s[2..4])?,
+                address: parse_hex(&s[4..6])?,
+            });
+        }
+
         if s.len() != 8 {
             return Err(InvalidAddress);
         }
@@ -258,7 +514,7 @@ impl FromStr for Address {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PacketInfo {
     /// dunno what this is?
     pub info: u1,
@@ -305,7 +561,8 @@ impl Default for PacketInfo {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Display, PartialEq, Eq, Hash, Clone, Copy)]
+#[display("{}", self.name())]
 #[repr(u8)]
 pub enum PacketType {
     StandBy = 0,
@@ -330,9 +587,44 @@ impl PacketType {
     pub fn to_u4(self) -> u4 {
         u4::new(self as u8)
     }
+
+    pub const ALL: [PacketType; 5] = [
+        PacketType::StandBy,
+        PacketType::Normal,
+        PacketType::Gathering,
+        PacketType::Install,
+        PacketType::Download,
+    ];
+
+    /// Stable lowercase name, as used by [`Display`] and [`FromStr`]
+    pub fn name(self) -> &'static str {
+        match self {
+            PacketType::StandBy => "standby",
+            PacketType::Normal => "normal",
+            PacketType::Gathering => "gathering",
+            PacketType::Install => "install",
+            PacketType::Download => "download",
+        }
+    }
+}
+
+#[derive(Display, Debug)]
+#[display("invalid packet type")]
+pub struct InvalidPacketType;
+
+/// Parses a packet type by name, ignoring case
+impl FromStr for PacketType {
+    type Err = InvalidPacketType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PacketType::ALL.into_iter()
+            .find(|typ| typ.name().eq_ignore_ascii_case(s))
+            .ok_or(InvalidPacketType)
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Display, PartialEq, Eq, Hash, Clone, Copy)]
+#[display("{}", self.name())]
 #[repr(u8)]
 pub enum DataType {
     Undefined = 0,
@@ -363,20 +655,91 @@ impl DataType {
     pub fn to_u4(self) -> u4 {
         u4::new(self as u8)
     }
+
+    pub const ALL: [DataType; 8] = [
+        DataType::Undefined,
+        DataType::Read,
+        DataType::Write,
+        DataType::Request,
+        DataType::Notification,
+        DataType::Response,
+        DataType::Ack,
+        DataType::Nack,
+    ];
+
+    /// Stable lowercase name, as used by [`Display`] and [`FromStr`]
+    pub fn name(self) -> &'static str {
+        match self {
+            DataType::Undefined => "undefined",
+            DataType::Read => "read",
+            DataType::Write => "write",
+            DataType::Request => "request",
+            DataType::Notification => "notification",
+            DataType::Response => "response",
+            DataType::Ack => "ack",
+            DataType::Nack => "nack",
+        }
+    }
+}
+
+#[derive(Display, Debug)]
+#[display("invalid data type")]
+pub struct InvalidDataType;
+
+/// Parses a data type by name, ignoring case
+impl FromStr for DataType {
+    type Err = InvalidDataType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DataType::ALL.into_iter()
+            .find(|typ| typ.name().eq_ignore_ascii_case(s))
+            .ok_or(InvalidDataType)
+    }
 }
 
-#[derive(Debug, Clone)]
+/// A single message within a packet. Prefer [`Message::new`] over
+/// constructing this directly, so that a value of the wrong kind for the
+/// message id is caught where the message is built rather than when the
+/// packet is serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Message {
     pub id: MessageId,
     pub value: Value,
 }
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+impl Message {
+    /// Creates a message, checking that the value kind matches the kind
+    /// encoded in the message id
+    pub fn new(id: MessageId, value: Value) -> Result<Self, WrongValueKind> {
+        if id.kind() != value.kind() {
+            return Err(WrongValueKind { expected: id.kind(), actual: value.kind() });
+        }
+
+        Ok(Message { id, value })
+    }
+
+    /// Whether this is message `M`, whatever its value
+    pub fn is<M: IsMessage>(&self) -> bool {
+        self.id == M::ID
+    }
+
+    /// Value of this message as `M`, if it is `M` and the value decodes
+    pub fn get<M: IsMessage>(&self) -> Option<M::Value> {
+        M::get(self)
+    }
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[debug("{:04x?}", self.0)]
 #[display("{:04x?}", self.0)]
 pub struct MessageId(pub u16);
 
 impl MessageId {
+    /// Catalog entry for this message, if it is a known message
+    pub fn metadata(&self) -> Option<&'static MessageMeta> {
+        crate::message::CATALOG.iter().find(|meta| meta.id == *self)
+    }
+
     pub fn kind(&self) -> MessageKind {
         match (self.0 & 0x0600) >> 9 {
             0 => MessageKind::Enum,
@@ -388,7 +751,35 @@ impl MessageId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Display, Debug)]
+#[display("invalid message id")]
+pub struct InvalidMessageId;
+
+/// Parses a message id as hex, with or without a leading `0x`, or by
+/// symbolic name as listed in [`crate::message::CATALOG`]
+impl FromStr for MessageId {
+    type Err = InvalidMessageId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(id) = crate::message::lookup_name(s) {
+            return Ok(id);
+        }
+
+        let hex = s.strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s);
+
+        if hex.is_empty() || hex.len() > 4 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(InvalidMessageId);
+        }
+
+        u16::from_str_radix(hex, 16)
+            .map(MessageId)
+            .map_err(|_| InvalidMessageId)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum MessageKind {
     Enum = 0,
@@ -397,18 +788,46 @@ pub enum MessageKind {
     Structure = 3,
 }
 
-#[derive(Debug, Clone, Copy, Display)]
+impl MessageKind {
+    /// Bytes taken by a message of this kind in a packet, including its
+    /// id. `None` for structures, which vary in size
+    pub const fn message_size(self) -> Option<usize> {
+        match self {
+            MessageKind::Enum => Some(2 + 1),
+            MessageKind::Variable => Some(2 + 2),
+            MessageKind::LongVariable => Some(2 + 4),
+            MessageKind::Structure => None,
+        }
+    }
+
+    /// Most messages of this kind that fit in one packet, limited by
+    /// both the frame size and the one byte message count. A structure
+    /// is always the only message in its packet
+    pub const fn max_per_packet(self) -> usize {
+        let Some(size) = self.message_size() else {
+            return 1;
+        };
+
+        let fit = MAX_PAYLOAD_SIZE / size;
+        if fit < MAX_MESSAGE_COUNT { fit } else { MAX_MESSAGE_COUNT }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Display, PartialEq, Eq, Hash)]
 pub enum Value {
+    #[display("0x{_0:02x} ({_0})")]
     Enum(u8),
+    #[display("0x{_0:04x} ({_0})")]
     Variable(u16),
+    #[display("0x{_0:08x} ({_0})")]
     LongVariable(u32),
 }
 
 #[derive(Debug, Error)]
 #[error("wrong value kind, expected {expected:?}, have {actual:?}")]
 pub struct WrongValueKind {
-    expected: MessageKind,
-    actual: MessageKind,
+    pub expected: MessageKind,
+    pub actual: MessageKind,
 }
 
 impl Value {
@@ -420,6 +839,24 @@ impl Value {
         }
     }
 
+    /// Widens any value kind to u32
+    pub fn as_u32(&self) -> u32 {
+        match *self {
+            Value::Enum(value) => u32::from(value),
+            Value::Variable(value) => u32::from(value),
+            Value::LongVariable(value) => value,
+        }
+    }
+
+    /// Value as a two's complement integer of the width of its kind
+    pub fn as_i32(&self) -> i32 {
+        match *self {
+            Value::Enum(value) => i32::from(value as i8),
+            Value::Variable(value) => i32::from(value as i16),
+            Value::LongVariable(value) => value as i32,
+        }
+    }
+
     fn wrong_value<T>(&self, expected: MessageKind) -> Result<T, WrongValueKind> {
         Err(WrongValueKind { expected, actual: self.kind() })
     }
@@ -436,7 +873,7 @@ impl Value {
         if let Value::Variable(value) = self {
             Ok(*value)
         } else {
-            self.wrong_value(MessageKind::Enum)
+            self.wrong_value(MessageKind::Variable)
         }
     }
 
@@ -444,12 +881,36 @@ impl Value {
         if let Value::LongVariable(value) = self {
             Ok(*value)
         } else {
-            self.wrong_value(MessageKind::Enum)
+            self.wrong_value(MessageKind::LongVariable)
         }
     }
 }
 
-#[derive(Debug)]
+impl TryFrom<Value> for u8 {
+    type Error = WrongValueKind;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.expect_u8()
+    }
+}
+
+impl TryFrom<Value> for u16 {
+    type Error = WrongValueKind;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.expect_u16()
+    }
+}
+
+impl TryFrom<Value> for u32 {
+    type Error = WrongValueKind;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.expect_u32()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Structure {
     pub number: MessageId,
     pub data: StructureData,
@@ -457,34 +918,37 @@ pub struct Structure {
 
 struct PacketReader<'a> {
     data: &'a [u8],
+    /// offset of `data` from the start of the packet
+    offset: usize,
 }
 
 impl<'a> PacketReader<'a> {
     pub fn new(data: &'a [u8]) -> Self {
-        PacketReader { data }
+        PacketReader { data, offset: 0 }
     }
 
-    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], PacketError> {
+    pub fn read_array<const N: usize>(&mut self, field: PacketField) -> Result<[u8; N], PacketError> {
         let (head, tail) = self.data.split_at_checked(N)
-            .ok_or(PacketError::TooShort)?;
+            .ok_or(PacketError::TooShort { field, offset: self.offset })?;
 
         let bytes = head.try_into().unwrap();
         self.data = tail;
+        self.offset += N;
 
         Ok(bytes)
     }
 
-    pub fn read_u8(&mut self) -> Result<u8, PacketError> {
-        let [byte] = self.read_array()?;
+    pub fn read_u8(&mut self, field: PacketField) -> Result<u8, PacketError> {
+        let [byte] = self.read_array(field)?;
         Ok(byte)
     }
 
-    pub fn read_u16(&mut self) -> Result<u16, PacketError> {
-        Ok(u16::from_be_bytes(self.read_array()?))
+    pub fn read_u16(&mut self, field: PacketField) -> Result<u16, PacketError> {
+        Ok(u16::from_be_bytes(self.read_array(field)?))
     }
 
-    pub fn read_u32(&mut self) -> Result<u32, PacketError> {
-        Ok(u32::from_be_bytes(self.read_array()?))
+    pub fn read_u32(&mut self, field: PacketField) -> Result<u32, PacketError> {
+        Ok(u32::from_be_bytes(self.read_array(field)?))
     }
 }
 
@@ -510,7 +974,7 @@ impl<'a> PacketWriter<'a> {
             .split_at_mut_checked(slice.len())
             .ok_or(SerializePacketError::BufferTooShort)?;
 
-        head.copy_from_slice(&slice);
+        head.copy_from_slice(slice);
         self.pos += slice.len();
 
         Ok(())
@@ -532,3 +996,126 @@ impl<'a> PacketWriter<'a> {
         self.write_array(u32::to_be_bytes(u))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIRE_BUFFER: usize = MAX_FRAME_SIZE + 16;
+
+    fn packet_of(kind_base: u16, value: Value, count: usize) -> Packet {
+        let messages = (0..count)
+            .map(|i| Message { id: MessageId(kind_base + i as u16), value })
+            .collect::<MessagesVec>();
+
+        Packet {
+            source: addresses::CONTROLLER,
+            destination: addresses::INDOOR,
+            packet_info: PacketInfo::default(),
+            packet_type: PacketType::Normal,
+            data_type: DataType::Request,
+            packet_number: 0,
+            data: Data::Messages(messages),
+        }
+    }
+
+    fn round_trip(packet: &Packet) -> Result<Packet, SerializePacketError> {
+        let mut wire = [0; WIRE_BUFFER];
+        let len = packet.serialize_frame(&mut wire)?;
+        assert_eq!(len, packet.serialized_len());
+        Ok(Packet::parse_wire(&wire[..len]).expect("parsing serialized packet"))
+    }
+
+    #[test]
+    fn typed_message_matching() {
+        use crate::message::{self, types::PowerSetting};
+
+        let msg = message::new::<message::Power>(PowerSetting::On);
+
+        assert!(msg.is::<message::Power>());
+        assert!(!msg.is::<message::Mode>());
+        assert_eq!(msg.get::<message::Power>(), Some(PowerSetting::On));
+
+        assert!(crate::matches_message!(&msg, message::Power => PowerSetting::On));
+        assert!(!crate::matches_message!(&msg, message::Power => PowerSetting::Off));
+        assert!(crate::matches_message!(&msg, message::Power => p if p != PowerSetting::Off));
+    }
+
+    #[test]
+    fn max_per_packet() {
+        assert_eq!(MessageKind::Enum.max_per_packet(), 255);
+        assert_eq!(MessageKind::Variable.max_per_packet(), 253);
+        assert_eq!(MessageKind::LongVariable.max_per_packet(), 169);
+        assert_eq!(MessageKind::Structure.max_per_packet(), 1);
+    }
+
+    #[test]
+    fn largest_packets_round_trip() {
+        let kinds = [
+            (0x4000, Value::Enum(1)),
+            (0x4200, Value::Variable(2)),
+            (0x8400, Value::LongVariable(3)),
+        ];
+
+        for (base, value) in kinds {
+            let packet = packet_of(base, value, value.kind().max_per_packet());
+            assert_eq!(round_trip(&packet).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn oversized_packets_are_refused() {
+        let kinds = [
+            (0x4200, Value::Variable(2)),
+            (0x8400, Value::LongVariable(3)),
+        ];
+
+        for (base, value) in kinds {
+            let count = value.kind().max_per_packet() + 1;
+            let packet = packet_of(base, value, count);
+
+            let Err(SerializePacketError::PacketTooLong { size }) = round_trip(&packet) else {
+                panic!("expected {count} {:?} messages to be too long", value.kind());
+            };
+
+            assert_eq!(size, HEADER_SIZE + count * value.kind().message_size().unwrap());
+        }
+    }
+
+    #[test]
+    fn largest_structure_round_trips() {
+        let packet = Packet {
+            data: Data::Structure(Structure {
+                number: MessageId(0x4600),
+                data: (0..MAX_STRUCTURE_SIZE).map(|i| i as u8).collect(),
+            }),
+            ..packet_of(0, Value::Enum(0), 0)
+        };
+
+        assert_eq!(round_trip(&packet).unwrap(), packet);
+    }
+
+    #[test]
+    fn wire_errors_count_from_start_of_wire() {
+        // a source address and the first 2 bytes of the destination
+        let data = [0x20, 0x00, 0x00, 0xb0, 0x00];
+
+        let mut wire = heapless::Vec::<u8, 32>::new();
+        wire.extend_from_slice(&FRAME_PREAMBLE).unwrap();
+        wire.push(FRAME_START).unwrap();
+        wire.extend_from_slice(&(data.len() as u16 + 4).to_be_bytes()).unwrap();
+        wire.extend_from_slice(&data).unwrap();
+        wire.extend_from_slice(&crc16(&data).to_be_bytes()).unwrap();
+        wire.push(FRAME_END).unwrap();
+
+        let offset = FRAME_PREAMBLE.len() + 3 + 3;
+
+        assert!(matches!(Packet::parse_wire(&wire),
+            Err(ParseWireError::Packet(PacketError::TooShort { field: PacketField::Destination, offset: at }))
+                if at == offset));
+
+        assert!(matches!(Packet::parse_wire(&wire[FRAME_PREAMBLE.len()..]),
+            Err(ParseWireError::Packet(PacketError::TooShort { offset: at, .. }))
+                if at == offset - FRAME_PREAMBLE.len()));
+    }
+}
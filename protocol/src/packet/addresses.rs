@@ -0,0 +1,75 @@
+//! Conventional addresses used on the bus. The first byte of an address is
+//! its class, identifying the kind of device; see [`class`].
+
+use derive_more::Display;
+
+use super::Address;
+
+/// Address classes, the first byte of an [`Address`]
+pub mod class {
+    pub const OUTDOOR: u8 = 0x10;
+    pub const INDOOR: u8 = 0x20;
+    pub const WIRED_REMOTE: u8 = 0x50;
+    pub const WIFI_KIT: u8 = 0x62;
+    /// Test and commissioning tools, used by this crate's client
+    pub const JIG_TESTER: u8 = 0x80;
+    /// Broadcast to all devices
+    pub const BROADCAST: u8 = 0xb0;
+}
+
+/// Decoded address class
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceClass {
+    #[display("outdoor unit")]
+    Outdoor,
+    #[display("indoor unit")]
+    Indoor,
+    #[display("wired remote")]
+    WiredRemote,
+    #[display("wifi kit")]
+    WifiKit,
+    #[display("jig tester")]
+    JigTester,
+    #[display("broadcast")]
+    Broadcast,
+    #[display("unknown class {_0:02x}")]
+    Other(u8),
+}
+
+impl DeviceClass {
+    pub fn from_u8(class: u8) -> Self {
+        match class {
+            class::OUTDOOR => DeviceClass::Outdoor,
+            class::INDOOR => DeviceClass::Indoor,
+            class::WIRED_REMOTE => DeviceClass::WiredRemote,
+            class::WIFI_KIT => DeviceClass::WifiKit,
+            class::JIG_TESTER => DeviceClass::JigTester,
+            class::BROADCAST => DeviceClass::Broadcast,
+            other => DeviceClass::Other(other),
+        }
+    }
+}
+
+impl Address {
+    pub fn device_class(&self) -> DeviceClass {
+        DeviceClass::from_u8(self.class)
+    }
+
+    /// Whether packets to this address go to many devices, none of which
+    /// reply
+    pub fn is_broadcast(&self) -> bool {
+        self.device_class() == DeviceClass::Broadcast
+    }
+}
+
+/// First outdoor unit
+pub const OUTDOOR: Address = Address { class: class::OUTDOOR, channel: 0x00, address: 0x00 };
+
+/// First indoor unit
+pub const INDOOR: Address = Address { class: class::INDOOR, channel: 0x00, address: 0x00 };
+
+/// Default source address of this crate's client
+pub const CONTROLLER: Address = Address { class: class::JIG_TESTER, channel: 0x10, address: 0x10 };
+
+/// Notifications are sent here, to be received by every device
+pub const BROADCAST: Address = Address { class: class::BROADCAST, channel: 0xff, address: 0xff };
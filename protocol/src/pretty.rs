@@ -1,9 +1,22 @@
-use crate::packet::{u1, u2, u3, Data, DataType, Packet, PacketType, Value};
+use crate::message::types::TempScale;
+use crate::packet::{u1, u2, u3, Data, DataType, MessageKind, Packet, PacketType};
 
 pub fn pretty_print(
     out: &mut dyn core::fmt::Write,
     packet: &Packet,
     use_color: bool,
+) -> core::fmt::Result {
+    pretty_print_in(out, packet, use_color, TempScale::Celsius)
+}
+
+/// Like [`pretty_print`], for packets to or from a unit that puts
+/// temperatures on the wire in `scale`. Scaled temperatures are converted
+/// to celsius, raw values are left as sent
+pub fn pretty_print_in(
+    out: &mut dyn core::fmt::Write,
+    packet: &Packet,
+    use_color: bool,
+    scale: TempScale,
 ) -> core::fmt::Result {
     let typ_color = color(use_color, match packet.data_type {
         DataType::Undefined => "",
@@ -48,12 +61,24 @@ pub fn pretty_print(
                 writeln!(out, "  (empty)")?;
             } else {
                 for msg in msgs {
-                    write!(out, "  {} => ", msg.id)?;
-                    match msg.value {
-                        Value::Enum(value) => writeln!(out, "0x{value:02x} ({value})")?,
-                        Value::Variable(value) => writeln!(out, "0x{value:04x} ({value})")?,
-                        Value::LongVariable(value) => writeln!(out, "0x{value:08x} ({value})")?,
+                    let meta = msg.id.metadata();
+
+                    write!(out, "  {}", msg.id)?;
+                    if let Some(meta) = meta {
+                        write!(out, " ({})", meta.name)?;
+                    }
+                    write!(out, " => {}", msg.value)?;
+                    if let Some(meta) = meta {
+                        if let Some(scaled) = meta.scaled_in(msg.value, scale) {
+                            write!(out, " = {scaled}")?;
+                        } else if meta.signed {
+                            write!(out, " = {}", meta.integer(msg.value))?;
+                        }
+                        if let Some(unit) = meta.unit {
+                            write!(out, " {unit}")?;
+                        }
                     }
+                    writeln!(out)?;
                 }
             }
         }
@@ -65,6 +90,102 @@ pub fn pretty_print(
     Ok(())
 }
 
+/// Renders a packet as a single line JSON object. Field names and layout
+/// are stable, so that all tools emitting JSON agree:
+///
+/// ```json
+/// {"source":"20.00.00","destination":"b0.ff.ff","packet_type":"normal",
+///  "data_type":"notification","packet_number":12,"retry_count":0,
+///  "messages":[{"id":"4201","name":"SetTemp","kind":"variable","value":250,
+///  "unit":"°C","scaled":25}]}
+/// ```
+///
+/// Structure packets carry a `"structure":{"id":..,"data":"<hex>"}` field
+/// in place of `"messages"`. `name`, `unit` and `scaled` are present only
+/// for messages known to the catalog.
+pub fn to_json(out: &mut dyn core::fmt::Write, packet: &Packet) -> core::fmt::Result {
+    to_json_in(out, packet, TempScale::Celsius)
+}
+
+/// Like [`to_json`], converting `scaled` temperatures to celsius as in
+/// [`pretty_print_in`]
+pub fn to_json_in(out: &mut dyn core::fmt::Write, packet: &Packet, scale: TempScale) -> core::fmt::Result {
+    write!(out, "{{\"source\":\"{}\",\"destination\":\"{}\"", packet.source, packet.destination)?;
+    write!(out, ",\"packet_type\":\"{}\"", packet.packet_type)?;
+    write!(out, ",\"data_type\":\"{}\"", packet.data_type)?;
+    write!(out, ",\"packet_number\":{}", packet.packet_number)?;
+    write!(out, ",\"retry_count\":{}", packet.packet_info.retry_count)?;
+
+    match &packet.data {
+        Data::Messages(msgs) => {
+            write!(out, ",\"messages\":[")?;
+            for (idx, msg) in msgs.iter().enumerate() {
+                if idx > 0 {
+                    write!(out, ",")?;
+                }
+
+                write!(out, "{{\"id\":\"{}\"", msg.id)?;
+
+                let meta = msg.id.metadata();
+                if let Some(meta) = meta {
+                    write!(out, ",\"name\":")?;
+                    json_string(out, meta.name)?;
+                }
+
+                write!(out, ",\"kind\":\"{}\"", kind_name(msg.value.kind()))?;
+                match meta {
+                    Some(meta) => write!(out, ",\"value\":{}", meta.integer(msg.value))?,
+                    None => write!(out, ",\"value\":{}", msg.value.as_u32())?,
+                }
+
+                if let Some(meta) = meta {
+                    if let Some(unit) = meta.unit {
+                        write!(out, ",\"unit\":")?;
+                        json_string(out, unit)?;
+                    }
+                    if let Some(scaled) = meta.scaled_in(msg.value, scale) {
+                        write!(out, ",\"scaled\":{scaled}")?;
+                    }
+                }
+
+                write!(out, "}}")?;
+            }
+            write!(out, "]")?;
+        }
+        Data::Structure(structure) => {
+            write!(out, ",\"structure\":{{\"id\":\"{}\",\"data\":\"", structure.number)?;
+            for byte in &structure.data {
+                write!(out, "{byte:02x}")?;
+            }
+            write!(out, "\"}}")?;
+        }
+    }
+
+    write!(out, "}}")
+}
+
+fn json_string(out: &mut dyn core::fmt::Write, s: &str) -> core::fmt::Result {
+    write!(out, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{c}")?,
+        }
+    }
+    write!(out, "\"")
+}
+
+fn kind_name(kind: MessageKind) -> &'static str {
+    match kind {
+        MessageKind::Enum => "enum",
+        MessageKind::Variable => "variable",
+        MessageKind::LongVariable => "long_variable",
+        MessageKind::Structure => "structure",
+    }
+}
+
 fn color(use_color: bool, s: &str) -> &str {
     if use_color {
         s